@@ -5,22 +5,22 @@ wget https://github.com/thewh1teagle/pyannote-rs/releases/download/v0.1.0/6_spea
 cargo run --example infinite 6_speakers.wav
 */
 
-use pyannote_rs::{EmbeddingExtractor, EmbeddingManager};
+use pyannote_rs::cluster::{Linkage, Stop};
+use pyannote_rs::{Adaptation, Aging, AgingAction, EmbeddingExtractor, EmbeddingManager};
 
 fn process_segment(
     segment: pyannote_rs::Segment,
     embedding_extractor: &mut EmbeddingExtractor,
     embedding_manager: &mut EmbeddingManager,
     search_threshold: f32,
+    window: &mut Vec<(f64, f64)>,
 ) -> Result<(), eyre::Report> {
-    let embedding_result: Vec<f32> = embedding_extractor
-        .compute(&segment.samples)
-        .unwrap()
-        .collect();
+    let embedding = embedding_extractor.compute(&segment.samples)?;
 
+    // No speaker limit, so every segment gets a speaker
     let speaker = embedding_manager
-        .search_speaker(embedding_result.clone(), search_threshold)
-        .ok_or_else(|| embedding_manager.search_speaker(embedding_result, 0.0)) // Ensure always to return speaker
+        .assign_segment(&embedding, search_threshold, segment.start, segment.end)?
+        .speaker_id()
         .map(|r| r.to_string())
         .unwrap_or("?".into());
 
@@ -28,10 +28,32 @@ fn process_segment(
         "start = {:.2}, end = {:.2}, speaker = {}",
         segment.start, segment.end, speaker
     );
+    window.push((segment.start, segment.end));
 
     Ok(())
 }
 
+/// Correct the labels of the segments since the last window and start a new
+/// one, so the recorded history stays bounded
+fn finalize_window(
+    embedding_manager: &mut EmbeddingManager,
+    search_threshold: f32,
+    window: &mut Vec<(f64, f64)>,
+) -> Result<(), eyre::Report> {
+    let labels =
+        embedding_manager.finalize(Linkage::Average, Stop::Threshold(1.0 - search_threshold))?;
+    println!("Corrected speakers:");
+    for ((start, end), speaker) in window.iter().zip(labels) {
+        println!(
+            "start = {:.2}, end = {:.2}, speaker = {}",
+            start, end, speaker
+        );
+    }
+    embedding_manager.clear_history();
+    window.clear();
+    Ok(())
+}
+
 fn main() -> Result<(), eyre::Report> {
     let audio_path = std::env::args().nth(1).expect("Please specify audio file");
     let search_threshold = 0.5;
@@ -41,17 +63,36 @@ fn main() -> Result<(), eyre::Report> {
 
     let (samples, sample_rate) = pyannote_rs::read_wav(&audio_path)?;
     let mut embedding_extractor = EmbeddingExtractor::new(embedding_model_path)?;
-    let mut embedding_manager = EmbeddingManager::new(usize::MAX);
+    // Refine speaker profiles as the conversation goes on, and keep the
+    // embeddings of the current window to correct its labels
+    let mut embedding_manager = EmbeddingManager::new(usize::MAX)
+        .with_adaptation(Adaptation::RunningMean)
+        .with_history()
+        // Forget speakers silent for an hour so the profiles stay bounded
+        .with_aging(Aging {
+            horizon: 3600.0,
+            action: AgingAction::Expire,
+        })?;
+    // Seconds of stream time per corrected window, finalizing is cubic in
+    // the number of segments
+    let window_length = 600.0;
+    let mut window_start = 0.0;
+    let mut window = Vec::new();
 
     let segments = pyannote_rs::get_segments(&samples, sample_rate, segmentation_model_path)?;
 
     for segment in segments {
         if let Ok(segment) = segment {
+            if segment.start - window_start >= window_length {
+                finalize_window(&mut embedding_manager, search_threshold, &mut window)?;
+                window_start = segment.start;
+            }
             if let Err(error) = process_segment(
                 segment,
                 &mut embedding_extractor,
                 &mut embedding_manager,
                 search_threshold,
+                &mut window,
             ) {
                 eprintln!("Error processing segment: {:?}", error);
             }
@@ -60,5 +101,5 @@ fn main() -> Result<(), eyre::Report> {
         }
     }
 
-    Ok(())
+    finalize_window(&mut embedding_manager, search_threshold, &mut window)
 }
@@ -1,4 +1,4 @@
-use pyannote_rs::{EmbeddingExtractor, EmbeddingManager};
+use pyannote_rs::{Assignment, EmbeddingExtractor, EmbeddingManager, OverflowPolicy};
 
 fn main() {
     let audio_path = std::env::args().nth(1).expect("Please specify audio file");
@@ -6,7 +6,8 @@ fn main() {
     let max_speakers = 6;
 
     let mut extractor = EmbeddingExtractor::new("wespeaker_en_voxceleb_CAM++.onnx").unwrap();
-    let mut manager = EmbeddingManager::new(6);
+    // Once all speakers are known, attribute new voices to the closest one
+    let mut manager = EmbeddingManager::new(max_speakers).with_overflow(OverflowPolicy::Nearest);
 
     let segments =
         pyannote_rs::get_segments(&samples, sample_rate, "segmentation-3.0.onnx").unwrap();
@@ -15,16 +16,15 @@ fn main() {
         match segment {
             Ok(segment) => {
                 if let Ok(embedding) = extractor.compute(&segment.samples) {
-                    let speaker = if manager.get_all_speakers().len() == max_speakers {
-                        manager
-                            .get_best_speaker_match(embedding.collect())
+                    let speaker = match manager.assign(&embedding, 0.5, 1.0) {
+                        Ok(Assignment::Nearest { speaker_id, .. }) => {
+                            format!("{} (nearest)", speaker_id)
+                        }
+                        Ok(assignment) => assignment
+                            .speaker_id()
                             .map(|s| s.to_string())
-                            .unwrap_or("?".into())
-                    } else {
-                        manager
-                            .search_speaker(embedding.collect(), 0.5)
-                            .map(|s| s.to_string())
-                            .unwrap_or("?".into())
+                            .unwrap_or("?".into()),
+                        Err(_) => "?".into(),
                     };
                     println!(
                         "start = {:.2}, end = {:.2}, speaker = {}",
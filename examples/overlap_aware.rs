@@ -0,0 +1,36 @@
+/*
+wget https://github.com/thewh1teagle/pyannote-rs/releases/download/v0.1.0/segmentation-3.0.onnx
+wget https://github.com/thewh1teagle/pyannote-rs/releases/download/v0.1.0/wespeaker_en_voxceleb_CAM++.onnx
+wget https://github.com/thewh1teagle/pyannote-rs/releases/download/v0.1.0/6_speakers.wav
+cargo run --example overlap_aware 6_speakers.wav
+*/
+
+use pyannote_rs::{EmbeddingExtractor, EmbeddingManager};
+
+fn main() -> Result<(), eyre::Report> {
+    let audio_path = std::env::args().nth(1).expect("Please specify audio file");
+    let (samples, sample_rate) = pyannote_rs::read_wav(&audio_path)?;
+
+    let mut extractor = EmbeddingExtractor::new("wespeaker_en_voxceleb_CAM++.onnx")?;
+    let mut manager = EmbeddingManager::new(usize::MAX);
+
+    let windows = pyannote_rs::get_windows(&samples, sample_rate, "segmentation-3.0.onnx")?;
+    for window in windows {
+        let window = window?;
+        // Only use frames where a single local speaker is talking
+        for local_speaker in window.active_speakers(20) {
+            let mask = window.exclusive_mask(local_speaker)?;
+            let embedding = extractor.compute_masked(&window.samples, &mask)?;
+            let speaker = manager
+                .search_speaker(&embedding, 0.5)?
+                .map(|s| s.to_string())
+                .unwrap_or("?".into());
+            println!(
+                "window start = {:.2}, local speaker = {}, speaker = {}",
+                window.start, local_speaker, speaker
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use pyannote_rs::EmbeddingExtractor;
+
+fn main() {
+    let enrolled_path = std::env::args()
+        .nth(1)
+        .expect("Please specify enrolled audio file");
+    let test_path = std::env::args()
+        .nth(2)
+        .expect("Please specify test audio file");
+    let threshold = std::env::args()
+        .nth(3)
+        .map(|t| t.parse::<f32>().expect("Invalid threshold"))
+        .unwrap_or(0.5);
+
+    let (enrolled, enrolled_rate) = pyannote_rs::read_wav(&enrolled_path).unwrap();
+    let (test, test_rate) = pyannote_rs::read_wav(&test_path).unwrap();
+    assert_eq!(
+        enrolled_rate, test_rate,
+        "Both clips must have the same sample rate"
+    );
+
+    let mut extractor = EmbeddingExtractor::new("wespeaker_en_voxceleb_CAM++.onnx").unwrap();
+    let verification = extractor
+        .verify(&enrolled, &test, enrolled_rate, threshold)
+        .unwrap();
+    println!(
+        "score = {:.3} {}",
+        verification.score,
+        if verification.accepted {
+            "accepted"
+        } else {
+            "rejected"
+        }
+    );
+}
@@ -0,0 +1,250 @@
+//! Conversation analytics from a diarization result.
+//!
+//! Consecutive segments of the same speaker separated by short pauses are
+//! merged into turns first, see [`AnalyticsOptions::max_pause`].
+
+use eyre::{bail, Result};
+use std::collections::HashMap;
+
+/// Speech of one speaker from `start` to `end` seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Turn {
+    pub start: f64,
+    pub end: f64,
+    pub speaker: usize,
+}
+
+impl Turn {
+    pub fn new(start: f64, end: f64, speaker: usize) -> Self {
+        Self {
+            start,
+            end,
+            speaker,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+/// Options for [`analyze`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalyticsOptions {
+    /// Consecutive segments of the same speaker at most this many seconds
+    /// apart form one turn
+    pub max_pause: f64,
+}
+
+impl Default for AnalyticsOptions {
+    fn default() -> Self {
+        Self { max_pause: 1.0 }
+    }
+}
+
+/// Statistics of one speaker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpeakerStats {
+    /// Seconds of speech
+    pub talk_time: f64,
+    pub turns: usize,
+    pub average_turn: f64,
+    /// Share of the total talk time of all speakers
+    pub talk_ratio: f64,
+    /// Turns started while someone else was talking, who then stopped first
+    pub interruptions: usize,
+    /// Times someone else took over while this speaker was talking
+    pub interrupted: usize,
+    /// Seconds spoken while someone else was talking too
+    pub overlap_time: f64,
+    /// Mean silence before this speaker answered someone else, `None`
+    /// without answers
+    pub response_latency: Option<f64>,
+}
+
+/// Output of [`analyze`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversationStats {
+    pub speakers: HashMap<usize, SpeakerStats>,
+    /// From the first start to the last end
+    pub duration: f64,
+    /// Seconds with at least one speaker
+    pub speech_time: f64,
+    /// Seconds with two or more speakers
+    pub overlap_time: f64,
+    pub silence_time: f64,
+}
+
+/// Compute per speaker and conversation statistics from speaker turns in any
+/// order
+pub fn analyze(turns: &[Turn], options: AnalyticsOptions) -> Result<ConversationStats> {
+    if let Some(turn) = turns
+        .iter()
+        .find(|turn| !(turn.start.is_finite() && turn.end.is_finite() && turn.start <= turn.end))
+    {
+        bail!("Invalid turn {}..{}", turn.start, turn.end)
+    }
+    let turns = merge_turns(turns, options.max_pause);
+    let Some(first) = turns.first() else {
+        return Ok(ConversationStats::default());
+    };
+
+    let mut stats = ConversationStats {
+        duration: turns.iter().map(|turn| turn.end).fold(f64::MIN, f64::max) - first.start,
+        ..Default::default()
+    };
+    for turn in &turns {
+        let speaker = stats.speakers.entry(turn.speaker).or_default();
+        speaker.talk_time += turn.duration();
+        speaker.turns += 1;
+    }
+
+    // Sweep over turn boundaries for speech and overlap time
+    let mut events: Vec<(f64, bool, usize)> = turns
+        .iter()
+        .flat_map(|turn| {
+            [
+                (turn.start, true, turn.speaker),
+                (turn.end, false, turn.speaker),
+            ]
+        })
+        .collect();
+    // Ends before starts at the same time, touching turns don't overlap
+    events.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut active: Vec<usize> = Vec::new();
+    let mut previous = first.start;
+    for (time, is_start, speaker) in events {
+        let elapsed = time - previous;
+        if !active.is_empty() {
+            stats.speech_time += elapsed;
+        }
+        if active.len() >= 2 {
+            stats.overlap_time += elapsed;
+            for speaker in &active {
+                if let Some(speaker) = stats.speakers.get_mut(speaker) {
+                    speaker.overlap_time += elapsed;
+                }
+            }
+        }
+        if is_start {
+            active.push(speaker);
+        } else if let Some(index) = active.iter().position(|&s| s == speaker) {
+            active.swap_remove(index);
+        }
+        previous = time;
+    }
+    stats.silence_time = stats.duration - stats.speech_time;
+
+    // Interruptions and response latency, comparing each turn with the ones
+    // that started before it
+    let mut latencies: HashMap<usize, Vec<f64>> = HashMap::new();
+    for (i, turn) in turns.iter().enumerate() {
+        for earlier in &turns[..i] {
+            if earlier.speaker != turn.speaker
+                && earlier.start < turn.start
+                && turn.start < earlier.end
+                && earlier.end < turn.end
+            {
+                stats
+                    .speakers
+                    .entry(turn.speaker)
+                    .or_default()
+                    .interruptions += 1;
+                stats
+                    .speakers
+                    .entry(earlier.speaker)
+                    .or_default()
+                    .interrupted += 1;
+            }
+        }
+        if let Some(previous) = i.checked_sub(1).map(|j| &turns[j]) {
+            if previous.speaker != turn.speaker && previous.end <= turn.start {
+                latencies
+                    .entry(turn.speaker)
+                    .or_default()
+                    .push(turn.start - previous.end);
+            }
+        }
+    }
+
+    let total_talk: f64 = stats.speakers.values().map(|s| s.talk_time).sum();
+    for (speaker_id, speaker) in stats.speakers.iter_mut() {
+        speaker.average_turn = speaker.talk_time / speaker.turns as f64;
+        if total_talk > 0.0 {
+            speaker.talk_ratio = speaker.talk_time / total_talk;
+        }
+        speaker.response_latency = latencies
+            .get(speaker_id)
+            .map(|latencies| latencies.iter().sum::<f64>() / latencies.len() as f64);
+    }
+    Ok(stats)
+}
+
+/// Merge consecutive segments of the same speaker separated by at most
+/// `max_pause` seconds, sorted by start. Anyone speaking in between starts a
+/// new turn.
+fn merge_turns(turns: &[Turn], max_pause: f64) -> Vec<Turn> {
+    let mut sorted = turns.to_vec();
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.speaker.cmp(&b.speaker)));
+    let mut merged: Vec<Turn> = Vec::with_capacity(sorted.len());
+    for turn in sorted {
+        match merged.last_mut() {
+            Some(last) if last.speaker == turn.speaker && turn.start - last.end <= max_pause => {
+                last.end = last.end.max(turn.end);
+            }
+            _ => merged.push(turn),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meeting_statistics() {
+        let turns = [
+            Turn::new(0.0, 4.0, 1),
+            Turn::new(4.5, 6.0, 1), // same turn after a short pause
+            Turn::new(7.0, 10.0, 2),
+            Turn::new(9.0, 12.0, 1), // interrupts speaker 2
+            Turn::new(14.0, 15.0, 2),
+        ];
+        let stats = analyze(&turns, AnalyticsOptions::default()).unwrap();
+
+        let (one, two) = (&stats.speakers[&1], &stats.speakers[&2]);
+        assert_eq!(one.turns, 2);
+        assert_eq!(one.talk_time, 9.0);
+        assert_eq!(two.talk_time, 4.0);
+        assert_eq!(one.interruptions, 1);
+        assert_eq!(two.interrupted, 1);
+        assert_eq!(one.overlap_time, 1.0);
+        assert_eq!(stats.overlap_time, 1.0);
+        assert_eq!(two.response_latency, Some(1.5));
+        assert_eq!(one.response_latency, None);
+        assert_eq!(stats.duration, 15.0);
+        assert_eq!(stats.speech_time, 12.0);
+        assert_eq!(stats.silence_time, 3.0);
+    }
+
+    #[test]
+    fn short_reply_splits_turns() {
+        let turns = [
+            Turn::new(0.0, 4.0, 1),
+            Turn::new(4.2, 4.4, 2),
+            Turn::new(4.5, 6.0, 1),
+        ];
+        let stats = analyze(&turns, AnalyticsOptions::default()).unwrap();
+
+        let (one, two) = (&stats.speakers[&1], &stats.speakers[&2]);
+        assert_eq!(one.turns, 2);
+        assert_eq!(two.turns, 1);
+        assert_eq!(two.interruptions, 0);
+        assert_eq!(one.interrupted, 0);
+        assert_eq!(two.overlap_time, 0.0);
+        assert_eq!(stats.overlap_time, 0.0);
+        assert!((two.response_latency.unwrap() - 0.2).abs() < 1e-9);
+        assert!((one.response_latency.unwrap() - 0.1).abs() < 1e-9);
+    }
+}
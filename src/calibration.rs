@@ -0,0 +1,247 @@
+//! Threshold calibration from labeled trials.
+//!
+//! Score same / different speaker pairs, then pick the operating point with
+//! [`calibrate`]. Thresholds follow [`crate::EmbeddingManager::search_speaker`]:
+//! a trial is accepted when its score is strictly above the threshold.
+
+use crate::cluster::check_dims;
+use crate::Embedding;
+use eyre::{bail, Result};
+
+/// Score of one trial and whether both sides are the same speaker
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trial {
+    pub score: f32,
+    pub target: bool,
+}
+
+/// Detection cost function parameters for [`calibrate`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostParams {
+    /// Prior probability of a target trial
+    pub p_target: f32,
+    pub c_miss: f32,
+    pub c_false_alarm: f32,
+}
+
+impl Default for CostParams {
+    fn default() -> Self {
+        Self {
+            p_target: 0.01,
+            c_miss: 1.0,
+            c_false_alarm: 1.0,
+        }
+    }
+}
+
+/// One operating point of the DET curve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetPoint {
+    pub threshold: f32,
+    /// Fraction of target trials rejected
+    pub p_miss: f32,
+    /// Fraction of non-target trials accepted
+    pub p_false_alarm: f32,
+}
+
+/// Output of [`calibrate`]
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    /// Equal error rate
+    pub eer: f32,
+    pub eer_threshold: f32,
+    /// Normalized minimum detection cost
+    pub min_dcf: f32,
+    pub min_dcf_threshold: f32,
+    /// Operating points from accepting everything to rejecting everything
+    pub det: Vec<DetPoint>,
+}
+
+impl Calibration {
+    /// Recommended `search_speaker` threshold, the one minimizing the
+    /// detection cost
+    pub fn threshold(&self) -> f32 {
+        self.min_dcf_threshold
+    }
+}
+
+/// Compute EER, minDCF and the DET curve of scored trials
+pub fn calibrate(trials: &[Trial], costs: CostParams) -> Result<Calibration> {
+    let targets = trials.iter().filter(|trial| trial.target).count();
+    let non_targets = trials.len() - targets;
+    if targets == 0 || non_targets == 0 {
+        bail!("Calibration needs both target and non-target trials")
+    }
+    if trials.iter().any(|trial| !trial.score.is_finite()) {
+        bail!("Trial scores must be finite")
+    }
+    if !(costs.p_target > 0.0 && costs.p_target < 1.0) {
+        bail!("p_target must be in (0, 1), got {}", costs.p_target)
+    }
+
+    let mut sorted = trials.to_vec();
+    sorted.sort_by(|a, b| a.score.total_cmp(&b.score));
+
+    // Sweep the threshold upwards, rejecting one more trial each step. Ties
+    // are kept on the same side.
+    let mut det = Vec::with_capacity(sorted.len() + 1);
+    let (mut misses, mut false_alarms) = (0, non_targets);
+    for i in 0..=sorted.len() {
+        if i > 0 {
+            if sorted[i - 1].target {
+                misses += 1;
+            } else {
+                false_alarms -= 1;
+            }
+        }
+        if i > 0 && i < sorted.len() && sorted[i].score == sorted[i - 1].score {
+            continue;
+        }
+        let threshold = match i {
+            0 => f32::NEG_INFINITY,
+            i if i == sorted.len() => sorted[i - 1].score,
+            i => (sorted[i - 1].score + sorted[i].score) / 2.0,
+        };
+        det.push(DetPoint {
+            threshold,
+            p_miss: misses as f32 / targets as f32,
+            p_false_alarm: false_alarms as f32 / non_targets as f32,
+        });
+    }
+
+    let dcf = |point: &DetPoint| {
+        costs.c_miss * costs.p_target * point.p_miss
+            + costs.c_false_alarm * (1.0 - costs.p_target) * point.p_false_alarm
+    };
+    let default_cost =
+        (costs.c_miss * costs.p_target).min(costs.c_false_alarm * (1.0 - costs.p_target));
+    let min_dcf_point = det
+        .iter()
+        .min_by(|a, b| dcf(a).total_cmp(&dcf(b)))
+        .copied()
+        .unwrap_or(det[0]);
+    let eer_point = det
+        .iter()
+        .min_by(|a, b| {
+            (a.p_miss - a.p_false_alarm)
+                .abs()
+                .total_cmp(&(b.p_miss - b.p_false_alarm).abs())
+        })
+        .copied()
+        .unwrap_or(det[0]);
+
+    Ok(Calibration {
+        eer: (eer_point.p_miss + eer_point.p_false_alarm) / 2.0,
+        eer_threshold: eer_point.threshold,
+        min_dcf: dcf(&min_dcf_point) / default_cost,
+        min_dcf_threshold: min_dcf_point.threshold,
+        det,
+    })
+}
+
+/// Cosine scored trials from embedding pairs labeled same speaker or not
+pub fn trials_from_pairs(pairs: &[(Embedding, Embedding, bool)]) -> Result<Vec<Trial>> {
+    pairs
+        .iter()
+        .map(|(a, b, target)| {
+            Ok(Trial {
+                score: a.cosine_similarity(b)?,
+                target: *target,
+            })
+        })
+        .collect()
+}
+
+/// Cosine scored trials from every pair of labeled segment embeddings
+pub fn trials_from_segments(embeddings: &[Embedding], labels: &[usize]) -> Result<Vec<Trial>> {
+    if embeddings.len() != labels.len() {
+        bail!(
+            "Got {} labels for {} embeddings",
+            labels.len(),
+            embeddings.len()
+        )
+    }
+    check_dims(embeddings)?;
+    let mut trials = Vec::new();
+    for i in 0..embeddings.len() {
+        for j in (i + 1)..embeddings.len() {
+            trials.push(Trial {
+                score: embeddings[i].cosine(&embeddings[j]),
+                target: labels[i] == labels[j],
+            });
+        }
+    }
+    Ok(trials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separable_scores_have_zero_error() {
+        let trials: Vec<Trial> = [0.9, 0.8, 0.7, 0.3, 0.2, 0.75]
+            .iter()
+            .enumerate()
+            .map(|(i, &score)| Trial {
+                score,
+                target: i < 3 || i == 5,
+            })
+            .collect();
+        let calibration = calibrate(&trials, CostParams::default()).unwrap();
+        assert_eq!(calibration.eer, 0.0);
+        assert_eq!(calibration.min_dcf, 0.0);
+        assert_eq!(calibration.threshold(), 0.5);
+        assert_eq!(calibration.det.len(), trials.len() + 1);
+    }
+
+    #[test]
+    fn overlapping_scores_with_ties() {
+        // Sorted: 0.1 N, 0.2 N, 0.3 T, 0.5 T, 0.5 N, 0.6 N, 0.7 T, 0.9 T
+        let trials: Vec<Trial> = [
+            (0.9, true),
+            (0.7, true),
+            (0.5, true),
+            (0.3, true),
+            (0.6, false),
+            (0.5, false),
+            (0.2, false),
+            (0.1, false),
+        ]
+        .iter()
+        .map(|&(score, target)| Trial { score, target })
+        .collect();
+        let costs = CostParams {
+            p_target: 0.5,
+            ..Default::default()
+        };
+        let calibration = calibrate(&trials, costs).unwrap();
+
+        // The tied trials are accepted or rejected together, so there is no
+        // point between them
+        let points: Vec<(f32, f32)> = calibration
+            .det
+            .iter()
+            .map(|point| (point.p_miss, point.p_false_alarm))
+            .collect();
+        assert_eq!(
+            points,
+            vec![
+                (0.0, 1.0),
+                (0.0, 0.75),
+                (0.0, 0.5),
+                (0.25, 0.5),
+                (0.5, 0.25),
+                (0.5, 0.0),
+                (0.75, 0.0),
+                (1.0, 0.0),
+            ]
+        );
+        // Closest to equal at 25% misses and 50% false alarms
+        assert_eq!(calibration.eer, 0.375);
+        assert!((calibration.eer_threshold - 0.4).abs() < 1e-6);
+        // 0.5 * 0 + 0.5 * 0.5 over the cost of always deciding the same, 0.5
+        assert_eq!(calibration.min_dcf, 0.5);
+        assert!((calibration.threshold() - 0.25).abs() < 1e-6);
+    }
+}
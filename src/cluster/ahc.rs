@@ -0,0 +1,214 @@
+use super::{check_dims, distances, prepare, relabel, SpeakerCount};
+use crate::{CosineScorer, Embedding, Prepared, Scorer};
+use eyre::Result;
+use ndarray::{Array1, Array2};
+
+/// How the distance between two clusters is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linkage {
+    /// Mean cosine distance between all members of both clusters
+    #[default]
+    Average,
+    /// Distance between the cluster centroids, the mean of their members
+    Centroid,
+}
+
+/// When agglomerative clustering stops merging
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// Stop once the closest clusters are at least this cosine distance apart
+    Threshold(f32),
+    /// Merge until exactly this many clusters are left
+    Clusters(usize),
+    /// Stop at `threshold` like [`Stop::Threshold`], but keep merging past
+    /// it while there are more than `speakers.max` clusters and never merge
+    /// below `speakers.min`
+    Bounded {
+        threshold: f32,
+        speakers: SpeakerCount,
+    },
+}
+
+struct Cluster {
+    size: usize,
+    /// Sum of the member embeddings
+    sum: Array1<f32>,
+    /// Mean of the members prepared by the scorer, for [`Linkage::Centroid`]
+    centroid: Option<Prepared>,
+}
+
+/// Cluster all segment embeddings of a recording with agglomerative
+/// hierarchical clustering over cosine distance.
+///
+/// Returns one label per embedding. Labels start at 0 and are numbered in
+/// order of first appearance.
+pub fn agglomerative(embeddings: &[Embedding], linkage: Linkage, stop: Stop) -> Result<Vec<usize>> {
+    agglomerative_with(embeddings, &CosineScorer, linkage, stop)
+}
+
+/// [`agglomerative`] over the distance `1 - score` of another [`Scorer`]
+pub fn agglomerative_with(
+    embeddings: &[Embedding],
+    scorer: &dyn Scorer,
+    linkage: Linkage,
+    stop: Stop,
+) -> Result<Vec<usize>> {
+    if let Stop::Bounded { speakers, .. } = stop {
+        speakers.check()?;
+    }
+    check_dims(embeddings)?;
+    let n = embeddings.len();
+
+    let prepared = prepare(embeddings, scorer)?;
+    let mut distances = distances(&prepared, scorer)?;
+    let mut clusters: Vec<Option<Cluster>> = embeddings
+        .iter()
+        .zip(prepared)
+        .map(|(embedding, prepared)| {
+            Some(Cluster {
+                size: 1,
+                sum: embedding.as_array().clone(),
+                centroid: (linkage == Linkage::Centroid).then_some(prepared),
+            })
+        })
+        .collect();
+    // Cluster each embedding currently belongs to
+    let mut assignment: Vec<usize> = (0..n).collect();
+    let mut remaining = n;
+
+    while remaining > 1 {
+        let Some((a, b, distance)) = closest_pair(&clusters, &distances) else {
+            break;
+        };
+        match stop {
+            Stop::Threshold(threshold) if distance >= threshold => break,
+            Stop::Clusters(target) if remaining <= target => break,
+            Stop::Bounded { speakers, .. } if remaining <= speakers.min => break,
+            Stop::Bounded {
+                threshold,
+                speakers,
+            } if distance >= threshold && remaining <= speakers.max => break,
+            _ => {}
+        }
+
+        // Merge b into a
+        let merged = clusters[b].take().expect("closest pair is active");
+        let (size_a, size_b) = (clusters[a].as_ref().map_or(0, |c| c.size), merged.size);
+        if let Some(cluster) = clusters[a].as_mut() {
+            cluster.size += merged.size;
+            cluster.sum += &merged.sum;
+            if linkage == Linkage::Centroid {
+                let mean = Embedding::from_array(&cluster.sum / cluster.size as f32)?;
+                cluster.centroid = Some(scorer.prepare(&mean)?);
+            }
+        }
+        for label in assignment.iter_mut().filter(|label| **label == b) {
+            *label = a;
+        }
+        remaining -= 1;
+
+        for k in 0..n {
+            if k == a || clusters[k].is_none() {
+                continue;
+            }
+            let distance = match linkage {
+                Linkage::Average => {
+                    (size_a as f32 * distances[[a, k]] + size_b as f32 * distances[[b, k]])
+                        / (size_a + size_b) as f32
+                }
+                Linkage::Centroid => {
+                    let centroid = |cluster: &Option<Cluster>| {
+                        cluster
+                            .as_ref()
+                            .and_then(|cluster| cluster.centroid.clone())
+                    };
+                    let (Some(x), Some(y)) = (centroid(&clusters[a]), centroid(&clusters[k]))
+                    else {
+                        continue;
+                    };
+                    1.0 - scorer.score_prepared(&x, &y)?
+                }
+            };
+            distances[[a, k]] = distance;
+            distances[[k, a]] = distance;
+        }
+    }
+
+    Ok(relabel(&assignment))
+}
+
+fn closest_pair(
+    clusters: &[Option<Cluster>],
+    distances: &Array2<f32>,
+) -> Option<(usize, usize, f32)> {
+    let mut best = None;
+    for i in 0..clusters.len() {
+        if clusters[i].is_none() {
+            continue;
+        }
+        for j in (i + 1)..clusters.len() {
+            if clusters[j].is_none() {
+                continue;
+            }
+            let distance = distances[[i, j]];
+            if best.is_none_or(|(_, _, best)| distance < best) {
+                best = Some((i, j, distance));
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embeddings(values: &[[f32; 2]]) -> Vec<Embedding> {
+        values
+            .iter()
+            .map(|v| Embedding::new(v.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn threshold_separates_directions() {
+        let embeddings = embeddings(&[[1.0, 0.0], [0.0, 1.0], [0.9, 0.1], [0.1, 0.9], [1.0, 0.05]]);
+        for linkage in [Linkage::Average, Linkage::Centroid] {
+            let labels = agglomerative(&embeddings, linkage, Stop::Threshold(0.3)).unwrap();
+            assert_eq!(labels, vec![0, 1, 0, 1, 0]);
+        }
+    }
+
+    #[test]
+    fn target_cluster_count() {
+        let embeddings = embeddings(&[[1.0, 0.0], [0.0, 1.0], [0.9, 0.1], [-1.0, 0.0]]);
+        let labels = agglomerative(&embeddings, Linkage::Average, Stop::Clusters(2)).unwrap();
+        assert_eq!(labels.iter().max(), Some(&1));
+        let labels = agglomerative(&embeddings, Linkage::Average, Stop::Clusters(1)).unwrap();
+        assert_eq!(labels, vec![0; 4]);
+    }
+
+    #[test]
+    fn bounded_speaker_count() {
+        let embeddings = embeddings(&[[1.0, 0.0], [0.0, 1.0], [0.9, 0.1], [-1.0, 0.0]]);
+        // The threshold alone keeps three clusters
+        let bounded = |speakers| Stop::Bounded {
+            threshold: 0.3,
+            speakers,
+        };
+        let labels = agglomerative(
+            &embeddings,
+            Linkage::Average,
+            bounded(SpeakerCount::at_most(2)),
+        )
+        .unwrap();
+        assert_eq!(labels.iter().max(), Some(&1));
+        let labels = agglomerative(
+            &embeddings,
+            Linkage::Average,
+            bounded(SpeakerCount::at_least(4)),
+        )
+        .unwrap();
+        assert_eq!(labels, vec![0, 1, 2, 3]);
+    }
+}
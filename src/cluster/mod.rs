@@ -0,0 +1,96 @@
+//! Offline clustering of all segment embeddings of a recording
+
+mod ahc;
+mod spectral;
+mod vbx;
+
+use crate::{Embedding, Prepared, Scorer};
+use eyre::{bail, Result};
+use ndarray::Array2;
+
+pub use ahc::{agglomerative, agglomerative_with, Linkage, Stop};
+pub use spectral::{spectral, spectral_with, SpectralOptions};
+pub use vbx::{vbx, vbx_embeddings, vbx_plda, VbxOptions, VbxResult};
+
+/// Bounds on the number of speakers in a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeakerCount {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl SpeakerCount {
+    pub fn exact(num_speakers: usize) -> Self {
+        Self::between(num_speakers, num_speakers)
+    }
+
+    pub fn between(min: usize, max: usize) -> Self {
+        Self { min, max }
+    }
+
+    pub fn at_least(min: usize) -> Self {
+        Self::between(min, usize::MAX)
+    }
+
+    pub fn at_most(max: usize) -> Self {
+        Self::between(1, max)
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.min == 0 || self.min > self.max {
+            bail!("Invalid speaker count {}..={}", self.min, self.max)
+        }
+        Ok(())
+    }
+}
+
+impl Default for SpeakerCount {
+    fn default() -> Self {
+        Self::at_least(1)
+    }
+}
+
+/// Pairwise distances `1 - score`, the cosine distance for
+/// [`crate::CosineScorer`]
+pub(crate) fn distances(prepared: &[Prepared], scorer: &dyn Scorer) -> Result<Array2<f32>> {
+    let n = prepared.len();
+    let mut distances = Array2::zeros((n, n));
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let distance = 1.0 - scorer.score_prepared(&prepared[i], &prepared[j])?;
+            distances[[i, j]] = distance;
+            distances[[j, i]] = distance;
+        }
+    }
+    Ok(distances)
+}
+
+/// Number labels in order of first appearance
+pub(crate) fn relabel(labels: &[usize]) -> Vec<usize> {
+    let mut mapping = std::collections::HashMap::new();
+    labels
+        .iter()
+        .map(|label| {
+            let next = mapping.len();
+            *mapping.entry(*label).or_insert(next)
+        })
+        .collect()
+}
+
+/// Embeddings prepared once for all pairs they are scored in
+pub(crate) fn prepare(embeddings: &[Embedding], scorer: &dyn Scorer) -> Result<Vec<Prepared>> {
+    embeddings
+        .iter()
+        .map(|embedding| scorer.prepare(embedding))
+        .collect()
+}
+
+/// Check that all embeddings have the same dimension
+pub(crate) fn check_dims(embeddings: &[Embedding]) -> Result<()> {
+    if let Some(first) = embeddings.first() {
+        for embedding in embeddings {
+            first.check_dim(embedding)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use super::{check_dims, prepare, relabel, SpeakerCount};
+use crate::linalg::symmetric_eigen;
+use crate::{CosineScorer, Embedding, Scorer};
+use eyre::{bail, Result};
+use ndarray::{Array2, Axis};
+
+/// Options for [`spectral`] clustering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpectralOptions {
+    /// Fraction of the strongest affinities kept per row (p-pruning)
+    pub p_pruning: f32,
+    pub speakers: SpeakerCount,
+}
+
+impl Default for SpectralOptions {
+    fn default() -> Self {
+        Self {
+            p_pruning: 0.2,
+            speakers: SpeakerCount::at_most(20),
+        }
+    }
+}
+
+/// Cluster segment embeddings with spectral clustering.
+///
+/// Builds a p-pruned cosine affinity matrix and estimates the number of
+/// speakers from the largest eigengap of its normalized Laplacian, within
+/// the bounds of `speakers`. Returns one label per embedding, numbered
+/// in order of first appearance.
+pub fn spectral(embeddings: &[Embedding], options: SpectralOptions) -> Result<Vec<usize>> {
+    spectral_with(embeddings, &CosineScorer, options)
+}
+
+/// [`spectral`] with the affinities of another [`Scorer`]. Negative scores
+/// count as no affinity, fails if no pair has a positive score, e.g. with
+/// [`crate::EuclideanScorer`].
+pub fn spectral_with(
+    embeddings: &[Embedding],
+    scorer: &dyn Scorer,
+    options: SpectralOptions,
+) -> Result<Vec<usize>> {
+    let SpeakerCount { min, max } = options.speakers;
+    options.speakers.check()?;
+    if !(options.p_pruning > 0.0 && options.p_pruning <= 1.0) {
+        bail!("p_pruning must be in (0, 1], got {}", options.p_pruning)
+    }
+    check_dims(embeddings)?;
+
+    let n = embeddings.len();
+    if n <= min {
+        return Ok((0..n).collect());
+    }
+
+    let affinity = pruned_affinity(embeddings, scorer, options.p_pruning)?;
+    if affinity.iter().all(|&affinity| affinity == 0.0) {
+        bail!("No positive scores between embeddings, spectral clustering needs similarities")
+    }
+    let laplacian = normalized_laplacian(&affinity);
+    let (values, vectors) = symmetric_eigen(&laplacian);
+
+    // Eigengap: pick k with the largest jump between the k-th and (k+1)-th
+    // smallest eigenvalues
+    let max = max.min(n - 1).max(min);
+    let num_speakers = (min..=max)
+        .max_by(|&a, &b| (values[a] - values[a - 1]).total_cmp(&(values[b] - values[b - 1])))
+        .unwrap_or(min);
+
+    // Spectral embedding: rows of the first k eigenvectors, unit normalized
+    let mut points = vectors.slice(ndarray::s![.., ..num_speakers]).to_owned();
+    for mut row in points.axis_iter_mut(Axis(0)) {
+        let norm = row.dot(&row).sqrt();
+        if norm > 0.0 {
+            row /= norm;
+        }
+    }
+
+    Ok(relabel(&kmeans(&points, num_speakers)))
+}
+
+/// Affinity keeping only the strongest `p` fraction of each row,
+/// symmetrized
+fn pruned_affinity(embeddings: &[Embedding], scorer: &dyn Scorer, p: f32) -> Result<Array2<f64>> {
+    let n = embeddings.len();
+    let prepared = prepare(embeddings, scorer)?;
+    let mut affinity = Array2::zeros((n, n));
+    for i in 0..n {
+        for j in i..n {
+            let similarity = scorer.score_prepared(&prepared[i], &prepared[j])?.max(0.0) as f64;
+            affinity[[i, j]] = similarity;
+            affinity[[j, i]] = similarity;
+        }
+    }
+
+    let keep = ((p * n as f32).ceil() as usize).clamp(2.min(n), n);
+    let mut pruned = Array2::zeros((n, n));
+    for (i, row) in affinity.axis_iter(Axis(0)).enumerate() {
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| row[b].total_cmp(&row[a]));
+        for &j in &order[..keep] {
+            pruned[[i, j]] = row[j];
+        }
+    }
+    Ok((&pruned + &pruned.t()) / 2.0)
+}
+
+/// I - D^-1/2 A D^-1/2
+fn normalized_laplacian(affinity: &Array2<f64>) -> Array2<f64> {
+    let n = affinity.nrows();
+    let scale: Vec<f64> = affinity
+        .sum_axis(Axis(1))
+        .iter()
+        .map(|&degree| if degree > 0.0 { degree.powf(-0.5) } else { 0.0 })
+        .collect();
+    let mut laplacian = Array2::eye(n);
+    for i in 0..n {
+        for j in 0..n {
+            laplacian[[i, j]] -= scale[i] * affinity[[i, j]] * scale[j];
+        }
+    }
+    laplacian
+}
+
+/// Lloyd's k-means with deterministic farthest point initialization
+fn kmeans(points: &Array2<f64>, k: usize) -> Vec<usize> {
+    let n = points.nrows();
+    let distance = |i: usize, center: &ndarray::Array1<f64>| {
+        let diff = &points.row(i) - center;
+        diff.dot(&diff)
+    };
+
+    let mut centers = vec![points.row(0).to_owned()];
+    while centers.len() < k {
+        let farthest = (0..n)
+            .max_by(|&a, &b| {
+                let da = centers
+                    .iter()
+                    .map(|c| distance(a, c))
+                    .fold(f64::MAX, f64::min);
+                let db = centers
+                    .iter()
+                    .map(|c| distance(b, c))
+                    .fold(f64::MAX, f64::min);
+                da.total_cmp(&db)
+            })
+            .unwrap_or(0);
+        centers.push(points.row(farthest).to_owned());
+    }
+
+    let mut labels = vec![0; n];
+    for _ in 0..100 {
+        let mut changed = false;
+        for (i, label) in labels.iter_mut().enumerate() {
+            let nearest = (0..k)
+                .min_by(|&a, &b| distance(i, &centers[a]).total_cmp(&distance(i, &centers[b])))
+                .unwrap_or(0);
+            if nearest != *label {
+                *label = nearest;
+                changed = true;
+            }
+        }
+        for (c, center) in centers.iter_mut().enumerate() {
+            let members: Vec<usize> = (0..n).filter(|&i| labels[i] == c).collect();
+            if !members.is_empty() {
+                *center = points
+                    .select(Axis(0), &members)
+                    .mean_axis(Axis(0))
+                    .unwrap_or(center.clone());
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twenty noisy embeddings around each of three directions
+    fn three_speakers() -> Vec<Embedding> {
+        (0..60)
+            .map(|i| {
+                let mut values: Vec<f32> = (0..8)
+                    .map(|d| ((i * 31 + d * 17) % 11) as f32 * 0.03)
+                    .collect();
+                values[i % 3] += 1.0;
+                Embedding::new(values).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimates_speaker_count() {
+        let embeddings = three_speakers();
+        let labels = spectral(&embeddings, SpectralOptions::default()).unwrap();
+        assert_eq!(labels.iter().max(), Some(&2));
+        for (i, label) in labels.iter().enumerate() {
+            assert_eq!(*label, labels[i % 3]);
+        }
+    }
+
+    #[test]
+    fn honors_speaker_count() {
+        let embeddings = three_speakers();
+        let cluster = |speakers| {
+            let options = SpectralOptions {
+                speakers,
+                ..Default::default()
+            };
+            let labels = spectral(&embeddings, options).unwrap();
+            labels.iter().max().map_or(0, |max| max + 1)
+        };
+        assert_eq!(cluster(SpeakerCount::exact(2)), 2);
+        assert_eq!(cluster(SpeakerCount::exact(5)), 5);
+        assert!(cluster(SpeakerCount::at_least(4)) >= 4);
+        assert_eq!(cluster(SpeakerCount::at_least(2)), 3);
+        assert!(spectral(
+            &embeddings,
+            SpectralOptions {
+                speakers: SpeakerCount::between(3, 2),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_scorers_without_affinity() {
+        let embeddings = three_speakers();
+        let options = SpectralOptions::default();
+        assert!(spectral_with(&embeddings, &crate::EuclideanScorer, options).is_err());
+    }
+}
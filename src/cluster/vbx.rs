@@ -0,0 +1,315 @@
+use super::{check_dims, relabel};
+use crate::plda::transform_all;
+use crate::{Embedding, Plda};
+use eyre::{bail, Result};
+use ndarray::{Array1, Array2, Axis};
+
+/// Options for [`vbx`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VbxOptions {
+    /// Probability of staying with the same speaker between consecutive frames
+    pub loop_prob: f64,
+    /// Scale of the acoustic likelihoods
+    pub fa: f64,
+    /// Speaker regularization, larger values drop more speakers
+    pub fb: f64,
+    pub max_iters: usize,
+    /// Stop once the ELBO improves by less than this
+    pub epsilon: f64,
+    /// Sharpness of the initial posteriors built from the AHC labels
+    pub init_smoothing: f64,
+}
+
+impl Default for VbxOptions {
+    fn default() -> Self {
+        Self {
+            loop_prob: 0.99,
+            fa: 0.3,
+            fb: 17.0,
+            max_iters: 20,
+            epsilon: 1e-4,
+            init_smoothing: 7.0,
+        }
+    }
+}
+
+/// Output of [`vbx`]
+#[derive(Debug, Clone)]
+pub struct VbxResult {
+    /// Speaker label per frame, numbered in order of first appearance
+    pub labels: Vec<usize>,
+    /// Speaker posteriors per frame (frames x initial speakers)
+    pub posteriors: Array2<f64>,
+    /// Speaker priors, speakers VBx dropped end up near zero
+    pub priors: Array1<f64>,
+    /// Evidence lower bound after each iteration
+    pub elbo: Vec<f64>,
+}
+
+/// VBx: Bayesian HMM clustering of a sequence of x-vectors.
+///
+/// `features` are frame embeddings (frames x dim) in a space where the
+/// within-speaker covariance is identity and the between-speaker covariance
+/// is `diag(phi)`, e.g. after a PLDA transform. `init` is the initial
+/// speaker per frame, usually from [`super::agglomerative`].
+pub fn vbx(
+    features: &Array2<f64>,
+    phi: &Array1<f64>,
+    init: &[usize],
+    options: VbxOptions,
+) -> Result<VbxResult> {
+    let (frames, dim) = features.dim();
+    if init.len() != frames {
+        bail!("Got {} initial labels for {} frames", init.len(), frames)
+    }
+    if phi.len() != dim {
+        bail!("phi has {} values for {} dimensions", phi.len(), dim)
+    }
+    if frames == 0 {
+        return Ok(VbxResult {
+            labels: Vec::new(),
+            posteriors: Array2::zeros((0, 0)),
+            priors: Array1::zeros(0),
+            elbo: Vec::new(),
+        });
+    }
+
+    // Initial posteriors: softened one hot AHC labels
+    let init = relabel(init);
+    let speakers = init.iter().max().map_or(0, |max| max + 1);
+    let mut gamma = Array2::<f64>::zeros((frames, speakers));
+    for (t, &label) in init.iter().enumerate() {
+        let mut row = gamma.row_mut(t);
+        row[label] = options.init_smoothing;
+        let norm = log_sum_exp(row.iter().copied());
+        row.mapv_inplace(|v| (v - norm).exp());
+    }
+    let mut pi = Array1::from_elem(speakers, 1.0 / speakers as f64);
+
+    // Per frame constant of the log likelihood
+    let g: Array1<f64> = features
+        .axis_iter(Axis(0))
+        .map(|x| -0.5 * (x.dot(&x) + dim as f64 * (2.0 * std::f64::consts::PI).ln()))
+        .collect();
+    let rho = features * &phi.mapv(f64::sqrt);
+    let ratio = options.fa / options.fb;
+
+    let mut elbo = Vec::new();
+    for _ in 0..options.max_iters {
+        // Speaker models
+        let counts = gamma.sum_axis(Axis(0));
+        let mut inv_l = Array2::<f64>::zeros((speakers, dim));
+        for s in 0..speakers {
+            for d in 0..dim {
+                inv_l[[s, d]] = 1.0 / (1.0 + ratio * counts[s] * phi[d]);
+            }
+        }
+        let alpha = &inv_l * &gamma.t().dot(&rho) * ratio;
+
+        // Frame log likelihoods
+        let penalty = (&inv_l + &alpha.mapv(|a| a * a)).dot(phi) * 0.5;
+        let mut log_p = rho.dot(&alpha.t());
+        for (mut row, &g) in log_p.axis_iter_mut(Axis(0)).zip(g.iter()) {
+            row.zip_mut_with(&penalty, |v, &p| *v = options.fa * (*v - p + g));
+        }
+
+        let (posteriors, total, forward, backward) =
+            forward_backward(&log_p, &pi, options.loop_prob);
+        gamma = posteriors;
+
+        let regularization: f64 = inv_l
+            .iter()
+            .zip(alpha.iter())
+            .map(|(&l, &a)| l.ln() - l - a * a + 1.0)
+            .sum();
+        elbo.push(total + options.fb * 0.5 * regularization);
+
+        // Speaker priors
+        let mut next_pi = gamma.row(0).to_owned();
+        for t in 0..(frames - 1) {
+            let stay = log_sum_exp(forward.row(t).iter().copied());
+            for s in 0..speakers {
+                next_pi[s] += (1.0 - options.loop_prob)
+                    * pi[s]
+                    * (stay + log_p[[t + 1, s]] + backward[[t + 1, s]] - total).exp();
+            }
+        }
+        let sum = next_pi.sum();
+        pi = next_pi / sum;
+
+        if let [.., previous, current] = elbo[..] {
+            if current - previous < options.epsilon {
+                break;
+            }
+        }
+    }
+
+    let labels: Vec<usize> = gamma
+        .axis_iter(Axis(0))
+        .map(|row| {
+            (0..speakers)
+                .max_by(|&a, &b| row[a].total_cmp(&row[b]))
+                .unwrap_or(0)
+        })
+        .collect();
+
+    Ok(VbxResult {
+        labels: relabel(&labels),
+        posteriors: gamma,
+        priors: pi,
+        elbo,
+    })
+}
+
+/// Run [`vbx`] directly on embeddings without a PLDA model.
+///
+/// Embeddings are length normalized and centered, then whitened with the
+/// within-speaker variance estimated from `init`. The variance of the initial
+/// speaker means serves as `phi`.
+pub fn vbx_embeddings(
+    embeddings: &[Embedding],
+    init: &[usize],
+    options: VbxOptions,
+) -> Result<VbxResult> {
+    check_dims(embeddings)?;
+    if init.len() != embeddings.len() {
+        bail!(
+            "Got {} initial labels for {} embeddings",
+            init.len(),
+            embeddings.len()
+        )
+    }
+    let Some(first) = embeddings.first() else {
+        return vbx(&Array2::zeros((0, 0)), &Array1::zeros(0), init, options);
+    };
+
+    let mut features = Array2::<f64>::zeros((embeddings.len(), first.dim()));
+    for (mut row, embedding) in features.axis_iter_mut(Axis(0)).zip(embeddings) {
+        row.assign(&embedding.normalized().as_array().mapv(f64::from));
+    }
+    let mean = features
+        .mean_axis(Axis(0))
+        .unwrap_or(Array1::zeros(first.dim()));
+    features -= &mean;
+
+    // Per speaker means from the initial labels
+    let init = relabel(init);
+    let speakers = init.iter().max().map_or(0, |max| max + 1);
+    let mut means = Array2::<f64>::zeros((speakers, first.dim()));
+    let mut counts = vec![0.0; speakers];
+    for (row, &label) in features.axis_iter(Axis(0)).zip(&init) {
+        let mut mean = means.row_mut(label);
+        mean += &row;
+        counts[label] += 1.0;
+    }
+    for (mut mean, count) in means.axis_iter_mut(Axis(0)).zip(&counts) {
+        mean /= *count;
+    }
+
+    let mut within = Array1::<f64>::zeros(first.dim());
+    for (row, &label) in features.axis_iter(Axis(0)).zip(&init) {
+        let diff = &row - &means.row(label);
+        within += &(&diff * &diff);
+    }
+    let within = (within / embeddings.len() as f64).mapv(|v| v.max(MIN_VARIANCE));
+    let between = means
+        .mapv(|m| m * m)
+        .mean_axis(Axis(0))
+        .unwrap_or(Array1::zeros(first.dim()));
+
+    let scale = within.mapv(|v| 1.0 / v.sqrt());
+    let features = features * &scale;
+    let phi = (between / &within).mapv(|v| v.max(MIN_VARIANCE));
+    vbx(&features, &phi, &init, options)
+}
+
+/// Run [`vbx`] on embeddings projected into the space of a PLDA model,
+/// using its between-speaker variances as `phi`
+pub fn vbx_plda(
+    embeddings: &[Embedding],
+    plda: &Plda,
+    init: &[usize],
+    options: VbxOptions,
+) -> Result<VbxResult> {
+    let features = transform_all(plda, embeddings)?;
+    vbx(&features, plda.psi(), init, options)
+}
+
+/// Floor for estimated variances
+const MIN_VARIANCE: f64 = 1e-6;
+
+/// HMM forward backward in log domain with the VBx transition matrix
+/// `loop_prob * I + (1 - loop_prob) * pi`.
+///
+/// Returns posteriors, total log likelihood and forward / backward log
+/// probabilities.
+fn forward_backward(
+    log_likelihoods: &Array2<f64>,
+    pi: &Array1<f64>,
+    loop_prob: f64,
+) -> (Array2<f64>, f64, Array2<f64>, Array2<f64>) {
+    let (frames, speakers) = log_likelihoods.dim();
+    let log_pi = pi.mapv(|p| (p + 1e-8).ln());
+    let log_tr = Array2::from_shape_fn((speakers, speakers), |(i, j)| {
+        let stay = if i == j { loop_prob } else { 0.0 };
+        (stay + (1.0 - loop_prob) * pi[j] + 1e-8).ln()
+    });
+
+    let mut forward = Array2::zeros((frames, speakers));
+    let mut backward = Array2::zeros((frames, speakers));
+    forward
+        .row_mut(0)
+        .assign(&(&log_likelihoods.row(0) + &log_pi));
+    for t in 1..frames {
+        for j in 0..speakers {
+            forward[[t, j]] = log_likelihoods[[t, j]]
+                + log_sum_exp((0..speakers).map(|i| forward[[t - 1, i]] + log_tr[[i, j]]));
+        }
+    }
+    for t in (0..(frames - 1)).rev() {
+        for i in 0..speakers {
+            backward[[t, i]] = log_sum_exp(
+                (0..speakers)
+                    .map(|j| log_tr[[i, j]] + log_likelihoods[[t + 1, j]] + backward[[t + 1, j]]),
+            );
+        }
+    }
+
+    let total = log_sum_exp(forward.row(frames - 1).iter().copied());
+    let posteriors = (&forward + &backward).mapv(|v| (v - total).exp());
+    (posteriors, total, forward, backward)
+}
+
+fn log_sum_exp(values: impl Iterator<Item = f64> + Clone) -> f64 {
+    let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixes_initial_mistakes() {
+        // Two speakers taking turns, 20 frames each
+        let truth: Vec<usize> = (0..40).map(|t| t / 20).collect();
+        let embeddings: Vec<Embedding> = (0..40)
+            .map(|t| {
+                let mut values: Vec<f32> = (0..8)
+                    .map(|d| ((t * 31 + d * 17) % 11) as f32 * 0.03)
+                    .collect();
+                values[truth[t]] += 1.0;
+                Embedding::new(values).unwrap()
+            })
+            .collect();
+        let mut init = truth.clone();
+        init[5] = 1;
+        init[30] = 0;
+
+        let result = vbx_embeddings(&embeddings, &init, VbxOptions::default()).unwrap();
+        assert_eq!(result.labels, truth);
+    }
+}
@@ -1,43 +1,470 @@
+use crate::segment::{FRAME_SIZE, FRAME_START};
 use crate::session;
-use eyre::{Context, ContextCompat, Result};
-use ndarray::Array2;
-use ort::{session::Session, value::Tensor};
+use eyre::{bail, Context, ContextCompat, Result};
+use ndarray::{Array1, Array2, Axis};
+use ort::{
+    session::Session,
+    value::{DynValue, Tensor},
+};
 use std::path::Path;
 
+/// Fbank frame shift and window length in samples (10ms / 25ms at 16kHz)
+const FBANK_SHIFT: usize = 160;
+const FBANK_WINDOW: usize = 400;
+/// Mel bins computed by [`knf_rs::compute_fbank`]
+const FBANK_BINS: usize = 80;
+
+/// Fewest feature frames accepted by [`EmbeddingExtractor::compute_masked`]
+const MIN_MASKED_FRAMES: usize = 10;
+
+/// Speaker embedding vector produced by [`EmbeddingExtractor`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Vec<f32>", try_from = "Vec<f32>")
+)]
+pub struct Embedding(Array1<f32>);
+
+impl Embedding {
+    pub fn new(values: Vec<f32>) -> Result<Self> {
+        Self::from_array(Array1::from_vec(values))
+    }
+
+    pub fn from_array(values: Array1<f32>) -> Result<Self> {
+        if values.is_empty() {
+            bail!("Embedding is empty")
+        }
+        if values.iter().any(|v| !v.is_finite()) {
+            bail!("Embedding contains non finite values")
+        }
+        // Contiguous, so `as_slice` always succeeds
+        Ok(Self(values.as_standard_layout().into_owned()))
+    }
+
+    pub fn dim(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn as_array(&self) -> &Array1<f32> {
+        &self.0
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        self.0
+            .as_slice()
+            .expect("embeddings are stored in standard layout")
+    }
+
+    pub fn into_vec(self) -> Vec<f32> {
+        self.0.to_vec()
+    }
+
+    pub fn norm(&self) -> f32 {
+        self.0.dot(&self.0).sqrt()
+    }
+
+    /// Scale to unit L2 norm. Zero vectors are returned unchanged.
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        if norm == 0.0 {
+            return self.clone();
+        }
+        Self(&self.0 / norm)
+    }
+
+    pub fn check_dim(&self, other: &Embedding) -> Result<()> {
+        if self.dim() != other.dim() {
+            bail!(
+                "Embedding dimension mismatch: {} != {}",
+                self.dim(),
+                other.dim()
+            )
+        }
+        Ok(())
+    }
+
+    pub fn cosine_similarity(&self, other: &Embedding) -> Result<f32> {
+        self.check_dim(other)?;
+        Ok(self.cosine(other))
+    }
+
+    pub fn euclidean_distance(&self, other: &Embedding) -> Result<f32> {
+        self.check_dim(other)?;
+        Ok(self.euclidean(other))
+    }
+
+    /// Element-wise mean of embeddings with the same dimension
+    pub fn average(embeddings: &[Embedding]) -> Result<Self> {
+        let first = embeddings.first().context("No embeddings to average")?;
+        let mut sum = Array1::zeros(first.dim());
+        for embedding in embeddings {
+            first.check_dim(embedding)?;
+            sum += &embedding.0;
+        }
+        Ok(Self(sum / embeddings.len() as f32))
+    }
+
+    /// Cosine similarity without the dimension check
+    pub(crate) fn cosine(&self, other: &Embedding) -> f32 {
+        let norms = self.norm() * other.norm();
+        if norms == 0.0 {
+            return 0.0;
+        }
+        self.0.dot(&other.0) / norms
+    }
+
+    pub(crate) fn euclidean(&self, other: &Embedding) -> f32 {
+        let diff = &self.0 - &other.0;
+        diff.dot(&diff).sqrt()
+    }
+}
+
+impl From<Embedding> for Vec<f32> {
+    fn from(embedding: Embedding) -> Self {
+        embedding.into_vec()
+    }
+}
+
+impl TryFrom<Vec<f32>> for Embedding {
+    type Error = eyre::Report;
+
+    fn try_from(values: Vec<f32>) -> Result<Self> {
+        Self::new(values)
+    }
+}
+
+/// What an embedding model expects as input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingInput {
+    /// Fbank features (batch x frames x bins), e.g. wespeaker CAM++
+    Fbank,
+    /// Raw 16kHz waveform (batch x samples or batch x channel x samples),
+    /// e.g. pyannote's wespeaker export
+    Waveform,
+}
+
+impl EmbeddingInput {
+    /// Guess the input kind from the model's first input
+    fn detect(session: &Session) -> Result<Self> {
+        let input = session.inputs.first().context("Model has no inputs")?;
+        let name = input.name.to_lowercase();
+        if name.contains("feat") || name.contains("fbank") {
+            return Ok(Self::Fbank);
+        }
+        if name.contains("wav") || name.contains("audio") || name.contains("signal") {
+            return Ok(Self::Waveform);
+        }
+        let shape = input
+            .input_type
+            .tensor_shape()
+            .context("Model input is not a tensor")?;
+        Ok(match shape.len() {
+            2 => Self::Waveform,
+            // (batch, channel, samples) has a single channel and a dynamic
+            // length, (batch, frames, bins) has a fixed number of bins
+            3 if shape[1] == 1 && shape[2] < 0 => Self::Waveform,
+            _ => Self::Fbank,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct EmbeddingExtractor {
     session: Session,
+    input: EmbeddingInput,
+    input_name: String,
+    input_rank: usize,
+    feature_bins: Option<usize>,
+    output_name: String,
+    embedding_dim: Option<usize>,
+    model_id: String,
 }
 
 impl EmbeddingExtractor {
+    /// Load a model and detect its input kind from the input signature
     pub fn new<P: AsRef<Path>>(model_path: P) -> Result<Self> {
         let session = session::create_session(model_path.as_ref())?;
-        Ok(Self { session })
+        let input = EmbeddingInput::detect(&session)?;
+        Self::from_session(session, input, session::model_id(model_path.as_ref())?)
     }
 
-    pub fn compute(&mut self, samples: &[i16]) -> Result<impl Iterator<Item = f32>> {
-        // Convert to f32 precisely
-        let mut samples_f32 = vec![0.0; samples.len()];
-        knf_rs::convert_integer_to_float_audio(samples, &mut samples_f32);
-        let samples = &samples_f32;
+    /// Load a model with an explicit input kind
+    pub fn with_input<P: AsRef<Path>>(model_path: P, input: EmbeddingInput) -> Result<Self> {
+        let session = session::create_session(model_path.as_ref())?;
+        Self::from_session(session, input, session::model_id(model_path.as_ref())?)
+    }
+
+    fn from_session(session: Session, input: EmbeddingInput, model_id: String) -> Result<Self> {
+        let model_input = session
+            .inputs
+            .first()
+            .context("Embedding model has no inputs")?;
+        let ranks: &[usize] = match input {
+            EmbeddingInput::Fbank => &[3],
+            EmbeddingInput::Waveform => &[2, 3],
+        };
+        let input_shape = session::check_tensor(
+            "Embedding model input",
+            &model_input.name,
+            &model_input.input_type,
+            ranks,
+        )?;
+
+        let model_output = session
+            .outputs
+            .first()
+            .context("Embedding model has no outputs")?;
+        let output_shape = session::check_tensor(
+            "Embedding model output",
+            &model_output.name,
+            &model_output.output_type,
+            &[1, 2],
+        )?;
+
+        Ok(Self {
+            input,
+            input_name: model_input.name.clone(),
+            input_rank: input_shape.len(),
+            feature_bins: fixed_dim(&input_shape).filter(|_| input == EmbeddingInput::Fbank),
+            output_name: model_output.name.clone(),
+            embedding_dim: fixed_dim(&output_shape),
+            model_id,
+            session,
+        })
+    }
+
+    pub fn input(&self) -> EmbeddingInput {
+        self.input
+    }
+
+    /// Fingerprint of the model file. Embeddings are only comparable when
+    /// computed with the same model, see [`crate::EmbeddingManager::save`].
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Embedding size, if the model declares a fixed one
+    pub fn embedding_dim(&self) -> Option<usize> {
+        self.embedding_dim
+    }
+
+    pub fn compute(&mut self, samples: &[i16]) -> Result<Embedding> {
+        match self.input {
+            EmbeddingInput::Fbank => {
+                let features = self.compute_features(samples)?;
+                self.run(features)
+            }
+            EmbeddingInput::Waveform => self.run_waveform(to_float(samples)),
+        }
+    }
+
+    /// Compute an embedding from precomputed features (frames x bins), e.g.
+    /// from a streaming fbank frontend or a feature cache.
+    ///
+    /// Features are passed to the model as is, so they should be prepared
+    /// like [`knf_rs::compute_fbank`] does, including mean normalization.
+    pub fn compute_from_features(&mut self, features: Array2<f32>) -> Result<Embedding> {
+        if self.input != EmbeddingInput::Fbank {
+            bail!("Model takes {:?} input, not features", self.input)
+        }
+        if features.is_empty() {
+            bail!("The features array is empty")
+        }
+        if let Some(bins) = self.feature_bins {
+            if features.ncols() != bins {
+                bail!(
+                    "Model expects {} feature bins, got {}",
+                    bins,
+                    features.ncols()
+                )
+            }
+        }
+        self.run(features)
+    }
+
+    /// Compute an embedding from the frames of a segmentation window where
+    /// `mask` is set, e.g. [`crate::SegmentationWindow::exclusive_mask`].
+    ///
+    /// `mask` holds one entry per segmentation frame of `samples`. Frames
+    /// outside the mask are dropped before running the model, so overlapped
+    /// speech doesn't leak in. Fbank models drop feature frames computed over
+    /// the whole window and mean-normalize the kept ones again, waveform
+    /// models drop the samples themselves.
+    pub fn compute_masked(&mut self, samples: &[i16], mask: &[bool]) -> Result<Embedding> {
+        if mask.is_empty() {
+            bail!("The mask is empty")
+        }
+        let is_active = |sample: usize| {
+            let frame = sample.saturating_sub(FRAME_START) / FRAME_SIZE;
+            mask[frame.min(mask.len() - 1)]
+        };
+
+        match self.input {
+            EmbeddingInput::Fbank => {
+                let features = self.compute_features(samples)?;
+                let rows: Vec<usize> = (0..features.nrows())
+                    .filter(|&row| is_active(row * FBANK_SHIFT + FBANK_WINDOW / 2))
+                    .collect();
+                self.run(select_frames(&features, &rows)?)
+            }
+            EmbeddingInput::Waveform => {
+                let samples: Vec<f32> = to_float(samples)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(i, _)| is_active(i))
+                    .map(|(_, sample)| sample)
+                    .collect();
+                check_masked_frames(samples.len() / FBANK_SHIFT)?;
+                self.run_waveform(samples)
+            }
+        }
+    }
+
+    fn compute_features(&self, samples: &[i16]) -> Result<Array2<f32>> {
+        if let Some(bins) = self.feature_bins.filter(|&bins| bins != FBANK_BINS) {
+            bail!(
+                "Model expects {} feature bins but fbank computes {}, use compute_from_features",
+                bins,
+                FBANK_BINS
+            )
+        }
+        knf_rs::compute_fbank(&to_float(samples))
+    }
+
+    fn run(&mut self, features: Array2<f32>) -> Result<Embedding> {
+        let features = features.insert_axis(Axis(0)); // Add batch dimension
+        self.run_tensor(Tensor::from_array(features)?.into_dyn()) // takes ownership of `features`
+    }
 
-        let features: Array2<f32> = knf_rs::compute_fbank(samples)?;
-        let features = features.insert_axis(ndarray::Axis(0)); // Add batch dimension
-        let inputs = ort::inputs![
-        "feats" => Tensor::from_array(features)? // takes ownership of `features`
-        ];
+    fn run_waveform(&mut self, samples: Vec<f32>) -> Result<Embedding> {
+        if samples.is_empty() {
+            bail!("The samples array is empty")
+        }
+        let waveform = Array1::from_vec(samples).insert_axis(Axis(0)); // Add batch dimension
+        let waveform = match self.input_rank {
+            2 => waveform.into_dyn(),
+            _ => waveform.insert_axis(Axis(1)).into_dyn(), // Add channel dimension
+        };
+        self.run_tensor(Tensor::from_array(waveform)?.into_dyn())
+    }
 
-        let ort_outs = self.session.run(inputs)?;
+    fn run_tensor(&mut self, input: DynValue) -> Result<Embedding> {
+        let ort_outs = self
+            .session
+            .run(ort::inputs![self.input_name.as_str() => input])?;
         let ort_out = ort_outs
-            .get("embs")
+            .get(&self.output_name)
             .context("Output tensor not found")?
             .try_extract_tensor::<f32>()
             .context("Failed to extract tensor")?;
 
-        // Collect the tensor data into a Vec to own it
-        let embeddings: Vec<f32> = ort_out.1.iter().copied().collect();
+        Embedding::new(ort_out.1.to_vec())
+    }
+}
+
+/// Last dimension of a model shape, unless it's dynamic
+fn fixed_dim(shape: &[i64]) -> Option<usize> {
+    shape
+        .last()
+        .and_then(|&dim| usize::try_from(dim).ok())
+        .filter(|&dim| dim > 0)
+}
+
+fn to_float(samples: &[i16]) -> Vec<f32> {
+    // Convert to f32 precisely
+    let mut samples_f32 = vec![0.0; samples.len()];
+    knf_rs::convert_integer_to_float_audio(samples, &mut samples_f32);
+    samples_f32
+}
+
+/// Keep the feature frames at `rows`. Fbank features are normalized by the
+/// mean of the whole window, so they are centered on the kept frames again.
+fn select_frames(features: &Array2<f32>, rows: &[usize]) -> Result<Array2<f32>> {
+    check_masked_frames(rows.len())?;
+    let features = features.select(Axis(0), rows);
+    let mean = features
+        .mean_axis(Axis(0))
+        .context("No frames in the mask")?;
+    Ok(features - mean)
+}
+
+fn check_masked_frames(frames: usize) -> Result<()> {
+    if frames < MIN_MASKED_FRAMES {
+        bail!(
+            "Not enough active frames in mask ({} < {})",
+            frames,
+            MIN_MASKED_FRAMES
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_values() {
+        assert!(Embedding::new(Vec::new()).is_err());
+        assert!(Embedding::new(vec![1.0, f32::NAN]).is_err());
+        assert!(Embedding::new(vec![f32::INFINITY]).is_err());
+
+        // Reversed views are copied into a contiguous array
+        let reversed = Array1::from_vec(vec![1.0, 2.0, 3.0]).slice_move(ndarray::s![..;-1]);
+        let embedding = Embedding::from_array(reversed).unwrap();
+        assert_eq!(embedding.as_slice(), &[3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn vector_math() {
+        let a = Embedding::new(vec![3.0, 4.0]).unwrap();
+        let b = Embedding::new(vec![4.0, -3.0]).unwrap();
+        assert_eq!(a.normalized().as_slice(), &[0.6, 0.8]);
+        let zero = Embedding::new(vec![0.0, 0.0]).unwrap();
+        assert_eq!(zero.normalized(), zero);
+        assert_eq!(zero.cosine_similarity(&a).unwrap(), 0.0);
+
+        assert_eq!(a.cosine_similarity(&b).unwrap(), 0.0);
+        assert!((a.cosine_similarity(&a).unwrap() - 1.0).abs() < 1e-6);
+        assert!((a.euclidean_distance(&b).unwrap() - 50f32.sqrt()).abs() < 1e-6);
+        let other_dim = Embedding::new(vec![1.0, 0.0, 0.0]).unwrap();
+        assert!(a.cosine_similarity(&other_dim).is_err());
+        assert!(a.euclidean_distance(&other_dim).is_err());
+
+        let average = Embedding::average(&[a.clone(), b]).unwrap();
+        assert_eq!(average.as_slice(), &[3.5, 0.5]);
+        assert!(Embedding::average(&[]).is_err());
+        assert!(Embedding::average(&[a, other_dim]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let embedding = Embedding::new(vec![0.5, -1.0]).unwrap();
+        let json = serde_json::to_string(&embedding).unwrap();
+        assert_eq!(json, "[0.5,-1.0]");
+        assert_eq!(serde_json::from_str::<Embedding>(&json).unwrap(), embedding);
+        assert!(serde_json::from_str::<Embedding>("[]").is_err());
+    }
 
-        // Return an iterator over the Vec
-        Ok(embeddings.into_iter())
+    #[test]
+    fn masked_frames_are_centered() {
+        // Masked out frames are far off and would shift a window mean
+        let features = Array2::from_shape_fn((30, 2), |(row, bin)| {
+            if row % 3 == 0 {
+                100.0
+            } else {
+                (row + bin) as f32
+            }
+        });
+        let rows: Vec<usize> = (0..30).filter(|row| row % 3 != 0).collect();
+        let selected = select_frames(&features, &rows).unwrap();
+        assert_eq!(selected.nrows(), 20);
+        for mean in selected.mean_axis(Axis(0)).unwrap() {
+            assert!(mean.abs() < 1e-4);
+        }
+        assert_eq!(selected[[1, 0]] - selected[[0, 0]], 1.0);
+        assert!(select_frames(&features, &rows[..5]).is_err());
     }
 }
@@ -0,0 +1,139 @@
+use super::{Assignment, EmbeddingManager, Speaker};
+use crate::Embedding;
+use eyre::{bail, ContextCompat, Result};
+use std::collections::HashMap;
+
+/// What happens to speakers not heard within the aging horizon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AgingAction {
+    /// Forget them
+    #[default]
+    Expire,
+    /// Stop matching them but keep their profile, see
+    /// [`EmbeddingManager::restore`]
+    Archive,
+}
+
+/// Forget speakers of unbounded streams, see [`EmbeddingManager::with_aging`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aging {
+    /// Seconds of stream time after which a silent speaker ages out
+    pub horizon: f64,
+    pub action: AgingAction,
+}
+
+impl Aging {
+    pub(super) fn check(&self) -> Result<()> {
+        if !(self.horizon.is_finite() && self.horizon >= 0.0) {
+            bail!(
+                "Aging horizon must be finite and non-negative, got {}",
+                self.horizon
+            )
+        }
+        Ok(())
+    }
+}
+
+impl Speaker {
+    /// Stream time in seconds the speaker was last heard
+    pub fn last_heard(&self) -> f64 {
+        self.last_heard
+    }
+
+    /// Seconds of speech assigned with [`EmbeddingManager::assign_segment`]
+    pub fn speech_duration(&self) -> f64 {
+        self.speech_duration
+    }
+}
+
+impl EmbeddingManager {
+    /// Expire or archive speakers not heard for `aging.horizon` seconds of
+    /// stream time, so long running streams stay bounded and stale profiles
+    /// stop matching. Named speakers never age. Recorded history of aged
+    /// speakers is dropped, see [`EmbeddingManager::finalize`].
+    pub fn with_aging(mut self, aging: Aging) -> Result<Self> {
+        aging.check()?;
+        self.aging = Some(aging);
+        Ok(self)
+    }
+
+    /// Current stream time in seconds
+    pub fn now(&self) -> f64 {
+        self.now
+    }
+
+    /// Move the stream clock to `now` and age speakers out. Returns the
+    /// speakers that expired or were archived.
+    pub fn advance_to(&mut self, now: f64) -> Result<Vec<usize>> {
+        self.now = self.now.max(now);
+        let Some(aging) = self.aging else {
+            return Ok(Vec::new());
+        };
+        let mut aged: Vec<usize> = self
+            .speakers
+            .iter()
+            .filter(|(_, speaker)| {
+                speaker.name.is_none() && self.now - speaker.last_heard > aging.horizon
+            })
+            .map(|(&speaker_id, _)| speaker_id)
+            .collect();
+        aged.sort();
+        for &speaker_id in &aged {
+            if let Some(speaker) = self.speakers.remove(&speaker_id) {
+                if aging.action == AgingAction::Archive {
+                    self.archived.insert(speaker_id, speaker);
+                }
+            }
+            self.sync_index(speaker_id)?;
+        }
+        self.prune_history(&aged);
+        Ok(aged)
+    }
+
+    /// [`EmbeddingManager::assign`] for a segment of a stream from `start`
+    /// to `end` seconds. Advances the stream clock, weights the embedding by
+    /// the segment duration and tracks when speakers were last heard.
+    pub fn assign_segment(
+        &mut self,
+        embedding: &Embedding,
+        threshold: f32,
+        start: f64,
+        end: f64,
+    ) -> Result<Assignment> {
+        if start.is_nan() || end.is_nan() || start > end {
+            bail!("Invalid segment {}..{}", start, end)
+        }
+        self.advance_to(end)?;
+        let duration = end - start;
+        let assignment = self.assign(embedding, threshold, duration as f32)?;
+        if let Some(speaker) = assignment
+            .speaker_id()
+            .and_then(|speaker_id| self.speakers.get_mut(&speaker_id))
+        {
+            speaker.speech_duration += duration;
+        }
+        Ok(assignment)
+    }
+
+    /// Speakers archived by [`AgingAction::Archive`]
+    pub fn archived(&self) -> &HashMap<usize, Speaker> {
+        &self.archived
+    }
+
+    /// Drop all archived speakers and return them
+    pub fn take_archived(&mut self) -> HashMap<usize, Speaker> {
+        std::mem::take(&mut self.archived)
+    }
+
+    /// Bring an archived speaker back, e.g. after they were recognized by
+    /// other means
+    pub fn restore(&mut self, speaker_id: usize) -> Result<()> {
+        let mut speaker = self
+            .archived
+            .remove(&speaker_id)
+            .with_context(|| format!("Speaker {} is not archived", speaker_id))?;
+        speaker.last_heard = self.now;
+        self.speakers.insert(speaker_id, speaker);
+        self.sync_index(speaker_id)
+    }
+}
@@ -0,0 +1,149 @@
+use super::{EmbeddingManager, SpeakerRemap};
+use crate::Embedding;
+use eyre::Result;
+
+/// What [`EmbeddingManager`] does with a new voice once `max_speakers`
+/// speakers exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Leave the segment unassigned
+    #[default]
+    Reject,
+    /// Assign to the most similar speaker without adapting its profile
+    Nearest,
+    /// Report the segment as an unknown speaker
+    Unknown,
+    /// Forget the speaker assigned least recently to make room. Named speakers
+    /// are never evicted.
+    EvictLeastRecent,
+}
+
+/// Result of [`EmbeddingManager::assign`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Assignment {
+    /// Matched an existing speaker above the threshold
+    Matched { speaker_id: usize, score: f32 },
+    /// Created a new speaker. `score` is the best existing speaker, which
+    /// fell below the threshold.
+    Created {
+        speaker_id: usize,
+        score: Option<f32>,
+    },
+    /// Over capacity, assigned to the most similar speaker by
+    /// [`OverflowPolicy::Nearest`]
+    Nearest { speaker_id: usize, score: f32 },
+    /// Over capacity, created a new speaker after evicting one by
+    /// [`OverflowPolicy::EvictLeastRecent`]. `remap` rewrites segments of
+    /// the evicted speaker.
+    Evicted {
+        speaker_id: usize,
+        evicted: usize,
+        remap: SpeakerRemap,
+        score: Option<f32>,
+    },
+    /// Over capacity, reported as unknown by [`OverflowPolicy::Unknown`]
+    Unknown,
+    /// Over capacity, left unassigned by [`OverflowPolicy::Reject`]
+    Rejected,
+}
+
+impl Assignment {
+    pub fn speaker_id(&self) -> Option<usize> {
+        match *self {
+            Assignment::Matched { speaker_id, .. }
+            | Assignment::Created { speaker_id, .. }
+            | Assignment::Nearest { speaker_id, .. }
+            | Assignment::Evicted { speaker_id, .. } => Some(speaker_id),
+            Assignment::Unknown | Assignment::Rejected => None,
+        }
+    }
+
+    /// Score against the assigned speaker. For new speakers the best score
+    /// of the existing ones, `None` if there were none.
+    pub fn score(&self) -> Option<f32> {
+        match *self {
+            Assignment::Matched { score, .. } | Assignment::Nearest { score, .. } => Some(score),
+            Assignment::Created { score, .. } | Assignment::Evicted { score, .. } => score,
+            Assignment::Unknown | Assignment::Rejected => None,
+        }
+    }
+}
+
+impl EmbeddingManager {
+    /// What to do with new voices once `max_speakers` is reached
+    pub fn with_overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Match `embedding` to the best speaker above `threshold`, or create a
+    /// new one, following the [`OverflowPolicy`] when full. `weight` is how
+    /// much the embedding counts when adapting the matched profile, usually
+    /// the segment duration.
+    pub fn assign(
+        &mut self,
+        embedding: &Embedding,
+        threshold: f32,
+        weight: f32,
+    ) -> Result<Assignment> {
+        Self::check_weight(weight)?;
+        self.check_dim(embedding)?;
+        let best = self.best_match(embedding)?;
+        let assignment = match best {
+            Some((speaker_id, score)) if score > threshold => {
+                self.update_speaker(speaker_id, embedding, weight)?;
+                Assignment::Matched { speaker_id, score }
+            }
+            _ if self.speakers.len() < self.max_speakers => Assignment::Created {
+                speaker_id: self.create_speaker(embedding, weight)?,
+                score: best.map(|(_, score)| score),
+            },
+            _ => match self.overflow {
+                OverflowPolicy::Reject => Assignment::Rejected,
+                OverflowPolicy::Unknown => Assignment::Unknown,
+                OverflowPolicy::Nearest => match best {
+                    Some((speaker_id, score)) => Assignment::Nearest { speaker_id, score },
+                    None => Assignment::Rejected,
+                },
+                OverflowPolicy::EvictLeastRecent => match self.least_recent() {
+                    Some(evicted) => {
+                        let remap = self.remove(evicted)?;
+                        Assignment::Evicted {
+                            speaker_id: self.create_speaker(embedding, weight)?,
+                            evicted,
+                            remap,
+                            score: best.map(|(_, score)| score),
+                        }
+                    }
+                    None => Assignment::Rejected,
+                },
+            },
+        };
+
+        self.clock += 1;
+        if let Some(speaker) = assignment
+            .speaker_id()
+            .and_then(|speaker_id| self.speakers.get_mut(&speaker_id))
+        {
+            speaker.last_seen = self.clock;
+            speaker.last_heard = self.now;
+        }
+        self.record(embedding, weight, assignment.speaker_id());
+        Ok(assignment)
+    }
+
+    fn create_speaker(&mut self, embedding: &Embedding, weight: f32) -> Result<usize> {
+        let speaker_id = self.add_speaker(embedding.clone(), weight, None)?;
+        self.add_exemplars(speaker_id, std::slice::from_ref(embedding))?;
+        Ok(speaker_id)
+    }
+
+    /// Unnamed speaker assigned least recently
+    fn least_recent(&self) -> Option<usize> {
+        self.speakers
+            .iter()
+            .filter(|(_, speaker)| speaker.name.is_none())
+            .min_by_key(|(&speaker_id, speaker)| (speaker.last_seen, speaker_id))
+            .map(|(&speaker_id, _)| speaker_id)
+    }
+}
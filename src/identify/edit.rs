@@ -0,0 +1,190 @@
+use super::{Adaptation, EmbeddingManager};
+use crate::Embedding;
+use eyre::{bail, ContextCompat, Result};
+use ndarray::Array1;
+use std::collections::HashMap;
+
+/// Speaker id changes made by an edit on [`EmbeddingManager`], used to
+/// rewrite segments that were already emitted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpeakerRemap {
+    ids: HashMap<usize, Option<usize>>,
+}
+
+impl SpeakerRemap {
+    /// Current id of a previously emitted speaker id. Removed speakers map to
+    /// `None`, untouched ids map to themselves.
+    pub fn apply(&self, speaker_id: usize) -> Option<usize> {
+        match self.ids.get(&speaker_id) {
+            Some(new_id) => *new_id,
+            None => Some(speaker_id),
+        }
+    }
+
+    /// Only the ids that changed
+    pub fn changes(&self) -> &HashMap<usize, Option<usize>> {
+        &self.ids
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Combine with a remap from a later edit
+    pub fn and_then(mut self, later: &SpeakerRemap) -> Self {
+        for new_id in self.ids.values_mut() {
+            *new_id = new_id.and_then(|id| later.apply(id));
+        }
+        for (&old_id, &new_id) in &later.ids {
+            self.ids.entry(old_id).or_insert(new_id);
+        }
+        self
+    }
+
+    fn single(old_id: usize, new_id: Option<usize>) -> Self {
+        Self {
+            ids: HashMap::from([(old_id, new_id)]),
+        }
+    }
+}
+
+impl EmbeddingManager {
+    /// Merge speaker `other` into `keep`. Profiles are combined by weight and
+    /// `keep` takes over the name of `other` if it has none.
+    pub fn merge(&mut self, keep: usize, other: usize) -> Result<SpeakerRemap> {
+        if keep == other {
+            bail!("Can't merge speaker {} with itself", keep)
+        }
+        if !self.speakers.contains_key(&keep) {
+            bail!("Unknown speaker {}", keep)
+        }
+        let merged = self
+            .speakers
+            .remove(&other)
+            .with_context(|| format!("Unknown speaker {}", other))?;
+        let speaker = self
+            .speakers
+            .get_mut(&keep)
+            .with_context(|| format!("Unknown speaker {}", keep))?;
+        speaker.adapt(Adaptation::RunningMean, &merged.embedding, merged.weight);
+        speaker.last_seen = speaker.last_seen.max(merged.last_seen);
+        speaker.last_heard = speaker.last_heard.max(merged.last_heard);
+        speaker.speech_duration += merged.speech_duration;
+        if speaker.name.is_none() {
+            speaker.name = merged.name;
+        }
+        self.add_exemplars(keep, &merged.exemplars)?;
+        self.sync_index(keep)?;
+        self.sync_index(other)?;
+        Ok(SpeakerRemap::single(other, Some(keep)))
+    }
+
+    /// Move segments that were wrongly attributed to `speaker_id` into a
+    /// new speaker and return its id. `segments` are embeddings with the
+    /// weight they were assigned with, usually their duration, and are taken
+    /// out of the original profile by that weight. Segments to move must be
+    /// relabeled by the caller.
+    ///
+    /// If they were all of its speech, the original speaker is replaced by
+    /// the new one and the remap says so.
+    pub fn split(
+        &mut self,
+        speaker_id: usize,
+        segments: &[(Embedding, f32)],
+    ) -> Result<(usize, SpeakerRemap)> {
+        let embeddings: Vec<Embedding> = segments
+            .iter()
+            .map(|(embedding, _)| embedding.clone())
+            .collect();
+        let first = embeddings.first().context("No segments to split")?;
+        self.check_dim(first)?;
+        let mut sum = Array1::zeros(first.dim());
+        let mut moved = 0.0;
+        for (embedding, weight) in segments {
+            Self::check_weight(*weight)?;
+            first.check_dim(embedding)?;
+            sum += &(embedding.as_array() * *weight);
+            moved += weight;
+        }
+        if moved <= 0.0 {
+            bail!("Segments to split have no weight")
+        }
+        let profile = Embedding::from_array(sum / moved)?;
+        let source = self
+            .speakers
+            .get(&speaker_id)
+            .with_context(|| format!("Unknown speaker {}", speaker_id))?;
+        let remaining = source.weight - moved;
+        let rest = Some(remaining)
+            .filter(|&remaining| remaining > 0.0)
+            .and_then(|remaining| {
+                let sum = source.embedding.as_array() * source.weight;
+                Embedding::from_array((sum - profile.as_array() * moved) / remaining).ok()
+            });
+
+        let replaced = rest.is_none();
+        match rest {
+            Some(rest) => {
+                if self.speakers.len() >= self.max_speakers {
+                    bail!(
+                        "Can't split speaker {}, there are already {} speakers",
+                        speaker_id,
+                        self.max_speakers
+                    )
+                }
+                if let Some(source) = self.speakers.get_mut(&speaker_id) {
+                    source.embedding = rest;
+                    source.weight = remaining;
+                    source
+                        .exemplars
+                        .retain(|exemplar| !embeddings.contains(exemplar));
+                    source.changed();
+                }
+            }
+            None => {
+                self.speakers.remove(&speaker_id);
+            }
+        }
+        self.sync_index(speaker_id)?;
+        let new_id = self.add_speaker(profile, moved, None)?;
+        self.add_exemplars(new_id, &embeddings)?;
+        let remap = if replaced {
+            SpeakerRemap::single(speaker_id, Some(new_id))
+        } else {
+            SpeakerRemap::default()
+        };
+        Ok((new_id, remap))
+    }
+
+    /// Forget a speaker
+    pub fn remove(&mut self, speaker_id: usize) -> Result<SpeakerRemap> {
+        self.speakers
+            .remove(&speaker_id)
+            .with_context(|| format!("Unknown speaker {}", speaker_id))?;
+        self.sync_index(speaker_id)?;
+        Ok(SpeakerRemap::single(speaker_id, None))
+    }
+
+    /// Name a speaker, or clear its name with `None`
+    pub fn relabel(&mut self, speaker_id: usize, name: Option<&str>) -> Result<()> {
+        if let Some(existing) = name.and_then(|name| self.find_speaker(name)) {
+            if existing != speaker_id {
+                bail!(
+                    "{} is already speaker {}, merge them instead",
+                    name.unwrap_or_default(),
+                    existing
+                )
+            }
+        }
+        let speaker = self
+            .speakers
+            .get_mut(&speaker_id)
+            .with_context(|| format!("Unknown speaker {}", speaker_id))?;
+        speaker.name = name.map(str::to_string);
+        if name.is_none() && speaker.unknown.is_none() {
+            speaker.unknown = Some(self.next_unknown);
+            self.next_unknown += 1;
+        }
+        Ok(())
+    }
+}
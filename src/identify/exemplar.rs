@@ -0,0 +1,129 @@
+use super::{EmbeddingManager, Speaker};
+use crate::{Embedding, Prepared, Scorer};
+use eyre::Result;
+
+/// How the similarities to the exemplars of a speaker are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExemplarScoring {
+    /// Best matching exemplar
+    #[default]
+    Max,
+    Mean,
+    /// Mean of the `n` best matching exemplars
+    TopMean(usize),
+}
+
+/// Keep several exemplar embeddings per speaker, see
+/// [`EmbeddingManager::with_exemplars`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExemplarOptions {
+    /// Exemplars kept per speaker
+    pub max_exemplars: usize,
+    pub scoring: ExemplarScoring,
+}
+
+impl Default for ExemplarOptions {
+    fn default() -> Self {
+        Self {
+            max_exemplars: 8,
+            scoring: ExemplarScoring::Max,
+        }
+    }
+}
+
+impl Speaker {
+    /// Exemplar embeddings, empty unless the manager keeps exemplars
+    pub fn exemplars(&self) -> &[Embedding] {
+        &self.exemplars
+    }
+
+    /// Add an exemplar. Once full, the exemplar most similar to the others
+    /// is dropped so the set stays diverse. Returns the positions dropped
+    /// after the push, in order.
+    fn add_exemplar(&mut self, embedding: &Embedding, max_exemplars: usize) -> Vec<usize> {
+        self.changed();
+        self.exemplars.push(embedding.clone());
+        let mut dropped = Vec::new();
+        while self.exemplars.len() > max_exemplars.max(1) {
+            let redundancy = |i: usize| {
+                self.exemplars
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, other)| self.exemplars[i].cosine(other))
+                    .fold(f32::MIN, f32::max)
+            };
+            // `max_by` keeps the last of equal elements, so ties drop the
+            // latest exemplar, e.g. a duplicate of an older one
+            let most_redundant = (0..self.exemplars.len())
+                .max_by(|&a, &b| redundancy(a).total_cmp(&redundancy(b)))
+                .unwrap_or(0);
+            self.exemplars.remove(most_redundant);
+            dropped.push(most_redundant);
+        }
+        dropped
+    }
+}
+
+/// Score against the exemplars of a speaker, `None` without exemplars
+pub(super) fn exemplar_score(
+    scorer: &dyn Scorer,
+    query: &Prepared,
+    exemplars: &[Prepared],
+    scoring: ExemplarScoring,
+) -> Result<Option<f32>> {
+    let mut scores = exemplars
+        .iter()
+        .map(|exemplar| scorer.score_prepared(query, exemplar))
+        .collect::<Result<Vec<f32>>>()?;
+    scores.sort_by(|a, b| b.total_cmp(a));
+    let n = match scoring {
+        ExemplarScoring::Max => 1,
+        ExemplarScoring::Mean => scores.len(),
+        ExemplarScoring::TopMean(n) => n.max(1),
+    };
+    scores.truncate(n);
+    if scores.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(scores.iter().sum::<f32>() / scores.len() as f32))
+}
+
+impl EmbeddingManager {
+    /// Score speakers against a diverse set of exemplar embeddings instead
+    /// of a single profile, for speakers who sound different across
+    /// channels or emotional states. The profile is still kept for
+    /// adaptation and indexing.
+    pub fn with_exemplars(mut self, options: ExemplarOptions) -> Self {
+        self.exemplars = Some(options);
+        self
+    }
+
+    pub(super) fn add_exemplars(
+        &mut self,
+        speaker_id: usize,
+        embeddings: &[Embedding],
+    ) -> Result<()> {
+        let Some(options) = self.exemplars else {
+            return Ok(());
+        };
+        let Some(speaker) = self.speakers.get_mut(&speaker_id) else {
+            return Ok(());
+        };
+        for embedding in embeddings {
+            let last = speaker.exemplars.len();
+            let dropped = speaker.add_exemplar(embedding, options.max_exemplars);
+            // Mirror the change in the index, the profile comes first
+            let Some(index) = self.index.as_mut() else {
+                continue;
+            };
+            if dropped != [last] {
+                index.push(speaker_id, embedding)?;
+                for position in dropped {
+                    index.remove_at(speaker_id, 1 + position);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use super::EmbeddingManager;
+use crate::cluster::{agglomerative_with, Linkage, Stop};
+use crate::Embedding;
+use eyre::{bail, ContextCompat, Result};
+use std::collections::HashMap;
+
+/// One online assignment recorded for [`EmbeddingManager::finalize`]
+#[derive(Debug, Clone)]
+pub(super) struct Assigned {
+    /// Dropped once the speaker ages out, see [`EmbeddingManager::with_aging`]
+    embedding: Option<Embedding>,
+    weight: f32,
+    speaker_id: Option<usize>,
+}
+
+impl EmbeddingManager {
+    /// Record every embedding passed to `search_speaker*` / `identify` so
+    /// the online labels can be corrected later with
+    /// [`EmbeddingManager::finalize`]. History grows with the stream, call
+    /// [`EmbeddingManager::clear_history`] after finalizing a window of an
+    /// unbounded one.
+    pub fn with_history(mut self) -> Self {
+        self.history.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Number of recorded assignments
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear_history(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+    }
+
+    pub(super) fn record(&mut self, embedding: &Embedding, weight: f32, speaker_id: Option<usize>) {
+        if let Some(history) = self.history.as_mut() {
+            history.push(Assigned {
+                embedding: Some(embedding.clone()),
+                weight,
+                speaker_id,
+            });
+        }
+    }
+
+    /// Drop the recorded embeddings of speakers that aged out, keeping their
+    /// labels
+    pub(super) fn prune_history(&mut self, speaker_ids: &[usize]) {
+        let Some(history) = self.history.as_mut() else {
+            return;
+        };
+        for assigned in history {
+            if assigned
+                .speaker_id
+                .is_some_and(|speaker_id| speaker_ids.contains(&speaker_id))
+            {
+                assigned.embedding = None;
+            }
+        }
+    }
+
+    /// Re-cluster all recorded embeddings globally and return the corrected
+    /// speaker id of each one, in the order they were recorded. Clustering
+    /// uses the scorer of the manager, see [`crate::cluster::agglomerative_with`].
+    ///
+    /// Each cluster keeps the online id it shares the most speech with, so
+    /// labels only change where the online pass got them wrong. Clusters
+    /// without one get a new speaker built from their embeddings, or go to
+    /// the closest speaker once `max_speakers` exist. Existing profiles are
+    /// left as is.
+    ///
+    /// The corrected ids replace the recorded ones, so finalizing again
+    /// gives the same labels without creating more speakers. Speakers that
+    /// aged out keep their online labels.
+    pub fn finalize(&mut self, linkage: Linkage, stop: Stop) -> Result<Vec<usize>> {
+        let Some(mut history) = self.history.take() else {
+            bail!("History is not recorded, see EmbeddingManager::with_history")
+        };
+        let result = self.relabel_history(&history, linkage, stop);
+        if let Ok(labels) = &result {
+            for (assigned, &speaker_id) in history.iter_mut().zip(labels) {
+                assigned.speaker_id = Some(speaker_id);
+            }
+        }
+        self.history = Some(history);
+        result
+    }
+
+    fn relabel_history(
+        &mut self,
+        all: &[Assigned],
+        linkage: Linkage,
+        stop: Stop,
+    ) -> Result<Vec<usize>> {
+        let history: Vec<(usize, &Assigned, &Embedding)> = all
+            .iter()
+            .enumerate()
+            .filter_map(|(i, assigned)| Some((i, assigned, assigned.embedding.as_ref()?)))
+            .collect();
+        let embeddings: Vec<Embedding> = history
+            .iter()
+            .map(|(_, _, embedding)| (*embedding).clone())
+            .collect();
+        let clusters = if embeddings.is_empty() {
+            Vec::new()
+        } else {
+            agglomerative_with(&embeddings, self.scorer.as_ref(), linkage, stop)?
+        };
+        let num_clusters = clusters.iter().max().map_or(0, |max| max + 1);
+
+        // Speech shared by each cluster and online speaker
+        let mut overlap: HashMap<(usize, usize), f32> = HashMap::new();
+        for ((_, assigned, _), &cluster) in history.iter().zip(&clusters) {
+            if let Some(speaker_id) = assigned.speaker_id {
+                *overlap.entry((cluster, speaker_id)).or_default() += assigned.weight;
+            }
+        }
+        let mut pairs: Vec<((usize, usize), f32)> = overlap
+            .into_iter()
+            .filter(|((_, speaker_id), _)| self.speakers.contains_key(speaker_id))
+            .collect();
+        pairs.sort_by(|(a, wa), (b, wb)| wb.total_cmp(wa).then(a.cmp(b)));
+
+        let mut cluster_ids: Vec<Option<usize>> = vec![None; num_clusters];
+        let mut taken = Vec::new();
+        for ((cluster, speaker_id), _) in pairs {
+            if cluster_ids[cluster].is_none() && !taken.contains(&speaker_id) {
+                cluster_ids[cluster] = Some(speaker_id);
+                taken.push(speaker_id);
+            }
+        }
+
+        for (cluster, speaker_id) in cluster_ids.iter_mut().enumerate() {
+            if speaker_id.is_some() {
+                continue;
+            }
+            let members: Vec<Embedding> = clusters
+                .iter()
+                .zip(&embeddings)
+                .filter(|(&label, _)| label == cluster)
+                .map(|(_, embedding)| embedding.clone())
+                .collect();
+            let weight = history
+                .iter()
+                .zip(&clusters)
+                .filter(|(_, &label)| label == cluster)
+                .map(|((_, assigned, _), _)| assigned.weight)
+                .sum();
+            let profile = Embedding::average(&members)?;
+            if self.speakers.len() >= self.max_speakers {
+                let (nearest, _) = self
+                    .best_match(&profile)?
+                    .context("No speaker to attribute the cluster to")?;
+                *speaker_id = Some(nearest);
+                continue;
+            }
+            let new_id = self.add_speaker(profile, weight, None)?;
+            self.add_exemplars(new_id, &members)?;
+            *speaker_id = Some(new_id);
+        }
+
+        let mut labels: Vec<usize> = all
+            .iter()
+            .map(|assigned| assigned.speaker_id.unwrap_or_default())
+            .collect();
+        for ((i, _, _), &cluster) in history.iter().zip(&clusters) {
+            labels[*i] = cluster_ids[cluster].unwrap_or_default();
+        }
+        Ok(labels)
+    }
+}
@@ -0,0 +1,484 @@
+use super::SpeakerMatch;
+use crate::Embedding;
+use eyre::{bail, Result};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+/// Options for [`HnswIndex`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswOptions {
+    /// Links per node on the upper layers, twice as many on the bottom one
+    pub m: usize,
+    /// Candidate list size while inserting, higher builds a better graph
+    pub ef_construction: usize,
+    /// Candidate list size while searching, higher trades speed for recall
+    pub ef_search: usize,
+    /// Seed for the layer assignment, fixed so builds are reproducible
+    pub seed: u64,
+}
+
+impl Default for HnswOptions {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+/// Approximate nearest neighbor index over cosine similarity
+/// (Hierarchical Navigable Small World graph).
+///
+/// An id can hold several embeddings, e.g. the exemplars of a speaker, and
+/// matches through the closest one. They are addressed by position in
+/// insertion order.
+///
+/// Removed entries stay in the graph as tombstones to keep it connected and
+/// the graph is rebuilt once they outnumber live entries.
+#[derive(Debug, Clone)]
+pub struct HnswIndex {
+    options: HnswOptions,
+    nodes: Vec<Node>,
+    /// Nodes of each live id
+    slots: HashMap<usize, Vec<usize>>,
+    /// Number of live nodes
+    live: usize,
+    entry: Option<usize>,
+    rng: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    id: usize,
+    /// Unit length
+    vector: Vec<f32>,
+    /// Neighbors per layer, from the bottom
+    links: Vec<Vec<usize>>,
+    removed: bool,
+}
+
+/// Node with its distance to the query, ordered by distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate(f32, usize);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl HnswIndex {
+    pub fn new(options: HnswOptions) -> Result<Self> {
+        if options.m < 2 {
+            bail!("HNSW needs at least 2 links per node, got {}", options.m)
+        }
+        Ok(Self {
+            options,
+            nodes: Vec::new(),
+            slots: HashMap::new(),
+            live: 0,
+            entry: None,
+            rng: options.seed.max(1),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    /// Embedding dimension of the index, `None` while empty
+    pub fn dim(&self) -> Option<usize> {
+        self.nodes.first().map(|node| node.vector.len())
+    }
+
+    /// Add `embedding` under `id`, replacing what was stored for it before
+    pub fn insert(&mut self, id: usize, embedding: &Embedding) -> Result<()> {
+        self.insert_all(id, std::slice::from_ref(embedding))
+    }
+
+    /// Add all `embeddings` under `id`, replacing what was stored for it
+    /// before
+    pub fn insert_all(&mut self, id: usize, embeddings: &[Embedding]) -> Result<()> {
+        let dim = self
+            .dim()
+            .or_else(|| embeddings.first().map(Embedding::dim));
+        if let Some(dim) = dim {
+            if let Some(embedding) = embeddings.iter().find(|e| e.dim() != dim) {
+                bail!(
+                    "Embedding dimension mismatch: {} != {}",
+                    embedding.dim(),
+                    dim
+                )
+            }
+        }
+        self.remove(id);
+        for embedding in embeddings {
+            self.insert_vector(id, embedding.normalized().into_vec());
+        }
+        Ok(())
+    }
+
+    /// Add one more embedding under `id`, keeping what is stored for it
+    pub fn push(&mut self, id: usize, embedding: &Embedding) -> Result<()> {
+        self.check_dim(embedding)?;
+        self.insert_vector(id, embedding.normalized().into_vec());
+        Ok(())
+    }
+
+    /// Replace the embedding at `position` of `id`
+    pub fn replace(&mut self, id: usize, position: usize, embedding: &Embedding) -> Result<()> {
+        self.check_dim(embedding)?;
+        let Some(&old) = self.slots.get(&id).and_then(|slots| slots.get(position)) else {
+            bail!("No embedding {} for id {}", position, id)
+        };
+        let slot = self.insert_vector(id, embedding.normalized().into_vec());
+        if let Some(slots) = self.slots.get_mut(&id) {
+            slots.pop();
+            slots[position] = slot;
+        }
+        self.tombstone(&[old]);
+        Ok(())
+    }
+
+    /// Remove the embedding at `position` of `id`, returns whether it was
+    /// present
+    pub fn remove_at(&mut self, id: usize, position: usize) -> bool {
+        let Some(slots) = self.slots.get_mut(&id) else {
+            return false;
+        };
+        if position >= slots.len() {
+            return false;
+        }
+        let slot = slots.remove(position);
+        if slots.is_empty() {
+            self.slots.remove(&id);
+        }
+        self.tombstone(&[slot]);
+        true
+    }
+
+    /// Remove `id`, returns whether it was present
+    pub fn remove(&mut self, id: usize) -> bool {
+        let Some(slots) = self.slots.remove(&id) else {
+            return false;
+        };
+        self.tombstone(&slots);
+        true
+    }
+
+    /// Mark nodes no longer referenced by an id as removed
+    fn tombstone(&mut self, slots: &[usize]) {
+        for &slot in slots {
+            self.nodes[slot].removed = true;
+        }
+        self.live -= slots.len();
+        if self.live == 0 {
+            self.nodes.clear();
+            self.entry = None;
+        } else if self.nodes.len() > 2 * self.live {
+            self.rebuild();
+        }
+    }
+
+    fn check_dim(&self, embedding: &Embedding) -> Result<()> {
+        match self.dim() {
+            Some(dim) if dim != embedding.dim() => bail!(
+                "Embedding dimension mismatch: {} != {}",
+                embedding.dim(),
+                dim
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Up to `k` ids most similar to `embedding`, best first, scored by
+    /// their closest embedding
+    pub fn search(&self, embedding: &Embedding, k: usize) -> Result<Vec<SpeakerMatch>> {
+        let Some(entry) = self.entry else {
+            return Ok(Vec::new());
+        };
+        if Some(embedding.dim()) != self.dim() {
+            bail!(
+                "Embedding dimension mismatch: {} != {}",
+                embedding.dim(),
+                self.dim().unwrap_or_default()
+            )
+        }
+        let query = embedding.normalized().into_vec();
+        let mut nearest = entry;
+        for level in (1..self.nodes[entry].links.len()).rev() {
+            nearest = self.search_layer(&query, &[nearest], 1, level)[0].1;
+        }
+        let found = self.search_layer(&query, &[nearest], self.options.ef_search.max(k), 0);
+        let mut matches: Vec<SpeakerMatch> = Vec::with_capacity(k.min(self.len()));
+        for Candidate(distance, slot) in found {
+            let node = &self.nodes[slot];
+            if node.removed || matches.iter().any(|m| m.speaker_id == node.id) {
+                continue;
+            }
+            matches.push(SpeakerMatch {
+                speaker_id: node.id,
+                score: 1.0 - distance,
+            });
+            if matches.len() == k {
+                break;
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Insert a node and append it to the slots of `id`
+    fn insert_vector(&mut self, id: usize, vector: Vec<f32>) -> usize {
+        let level = self.random_level();
+        let slot = self.nodes.len();
+        self.nodes.push(Node {
+            id,
+            vector,
+            links: vec![Vec::new(); level + 1],
+            removed: false,
+        });
+        self.slots.entry(id).or_default().push(slot);
+        self.live += 1;
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            return slot;
+        };
+        let query = self.nodes[slot].vector.clone();
+        let top = self.nodes[entry].links.len() - 1;
+
+        let mut nearest = entry;
+        for level in ((level + 1)..=top).rev() {
+            nearest = self.search_layer(&query, &[nearest], 1, level)[0].1;
+        }
+        let mut entry_points = vec![nearest];
+        for level in (0..=level.min(top)).rev() {
+            let found =
+                self.search_layer(&query, &entry_points, self.options.ef_construction, level);
+            let neighbors: Vec<usize> = found
+                .iter()
+                .take(self.options.m)
+                .map(|Candidate(_, neighbor)| *neighbor)
+                .collect();
+            for &neighbor in &neighbors {
+                self.nodes[neighbor].links[level].push(slot);
+                self.prune(neighbor, level);
+            }
+            self.nodes[slot].links[level] = neighbors;
+            entry_points = found.into_iter().map(|Candidate(_, node)| node).collect();
+        }
+
+        if level > top {
+            self.entry = Some(slot);
+        }
+        slot
+    }
+
+    /// Keep only the closest links of a node that has too many
+    fn prune(&mut self, slot: usize, level: usize) {
+        let max_links = if level == 0 {
+            2 * self.options.m
+        } else {
+            self.options.m
+        };
+        if self.nodes[slot].links[level].len() <= max_links {
+            return;
+        }
+        let vector = &self.nodes[slot].vector;
+        let mut links: Vec<Candidate> = self.nodes[slot].links[level]
+            .iter()
+            .map(|&neighbor| Candidate(self.distance(vector, neighbor), neighbor))
+            .collect();
+        links.sort();
+        links.truncate(max_links);
+        self.nodes[slot].links[level] = links.into_iter().map(|Candidate(_, node)| node).collect();
+    }
+
+    /// Best first search on one layer, returns up to `ef` nodes sorted by
+    /// distance
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Candidate> {
+        let mut visited = vec![false; self.nodes.len()];
+        for &slot in entry_points {
+            visited[slot] = true;
+        }
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &slot in entry_points {
+            let candidate = Candidate(self.distance(query, slot), slot);
+            candidates.push(Reverse(candidate));
+            found.push(candidate);
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Reverse(Candidate(distance, slot))) = candidates.pop() {
+            if found
+                .peek()
+                .is_some_and(|farthest: &Candidate| distance > farthest.0 && found.len() >= ef)
+            {
+                break;
+            }
+            let Some(links) = self.nodes[slot].links.get(level) else {
+                continue;
+            };
+            for &neighbor in links {
+                if std::mem::replace(&mut visited[neighbor], true) {
+                    continue;
+                }
+                let candidate = Candidate(self.distance(query, neighbor), neighbor);
+                if found.len() < ef || found.peek().is_some_and(|farthest| candidate < *farthest) {
+                    candidates.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    fn distance(&self, query: &[f32], slot: usize) -> f32 {
+        // Independent lanes so the compiler can vectorize the sum
+        let vector = &self.nodes[slot].vector;
+        let mut lanes = [0.0f32; 8];
+        for (a, b) in query.chunks_exact(8).zip(vector.chunks_exact(8)) {
+            for lane in 0..8 {
+                lanes[lane] += a[lane] * b[lane];
+            }
+        }
+        let tail = query.len() / 8 * 8;
+        let rest: f32 = query[tail..]
+            .iter()
+            .zip(&vector[tail..])
+            .map(|(a, b)| a * b)
+            .sum();
+        1.0 - lanes.iter().sum::<f32>() - rest
+    }
+
+    /// Geometric layer distribution with a factor of 1 / ln(m)
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = ((self.rng >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-uniform.ln() / (self.options.m as f64).ln()) as usize
+    }
+
+    /// Rebuild the graph from the live entries, dropping tombstones
+    fn rebuild(&mut self) {
+        let mut live: Vec<(usize, Vec<f32>)> = self
+            .slots
+            .values()
+            .flatten()
+            .map(|&slot| (self.nodes[slot].id, self.nodes[slot].vector.clone()))
+            .collect();
+        live.sort_by_key(|(id, _)| *id);
+        self.nodes.clear();
+        self.slots.clear();
+        self.live = 0;
+        self.entry = None;
+        for (id, vector) in live {
+            self.insert_vector(id, vector);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_nearest_neighbors() {
+        let mut state = 7u64;
+        let mut random = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        };
+        let embeddings: Vec<Embedding> = (0..500)
+            .map(|_| Embedding::new((0..16).map(|_| random()).collect()).unwrap())
+            .collect();
+        let mut index = HnswIndex::new(HnswOptions::default()).unwrap();
+        for (id, embedding) in embeddings.iter().enumerate() {
+            index.insert(id, embedding).unwrap();
+        }
+
+        let mut hits = 0;
+        for query in embeddings.iter().step_by(10) {
+            let exact = (0..embeddings.len())
+                .max_by(|&a, &b| {
+                    query
+                        .cosine(&embeddings[a])
+                        .total_cmp(&query.cosine(&embeddings[b]))
+                })
+                .unwrap();
+            let found = index.search(query, 1).unwrap();
+            hits += (found[0].speaker_id == exact) as usize;
+        }
+        assert!(hits >= 48, "recall {} / 50", hits);
+
+        for id in 0..400 {
+            assert!(index.remove(id));
+        }
+        assert_eq!(index.len(), 100);
+        let found = index.search(&embeddings[450], 5).unwrap();
+        assert_eq!(found[0].speaker_id, 450);
+        assert!(found.iter().all(|m| m.speaker_id >= 400));
+        assert_eq!(
+            index.search(&embeddings[450], usize::MAX).unwrap().len(),
+            100
+        );
+    }
+
+    #[test]
+    fn edits_embeddings_by_position() {
+        let embedding = |values: &[f32]| Embedding::new(values.to_vec()).unwrap();
+        let (x, y, z) = (
+            embedding(&[1.0, 0.0, 0.0]),
+            embedding(&[0.0, 1.0, 0.0]),
+            embedding(&[0.0, 0.0, 1.0]),
+        );
+        let mut index = HnswIndex::new(HnswOptions::default()).unwrap();
+        index.insert(1, &x).unwrap();
+        index.push(1, &y).unwrap();
+        index.insert(2, &embedding(&[-1.0, -1.0, -1.0])).unwrap();
+        assert_eq!(index.search(&y, 1).unwrap()[0].speaker_id, 1);
+
+        index.replace(1, 0, &z).unwrap();
+        assert_eq!(index.search(&z, 1).unwrap()[0].speaker_id, 1);
+        assert!(index.search(&x, 1).unwrap()[0].score < 0.5);
+        assert!(index.replace(1, 2, &z).is_err());
+
+        assert!(index.remove_at(1, 1));
+        assert!(index.search(&y, 1).unwrap()[0].score < 0.5);
+        assert!(index.remove_at(1, 0));
+        assert!(!index.contains(1));
+        assert_eq!(index.len(), 1);
+    }
+}
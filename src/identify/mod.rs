@@ -0,0 +1,946 @@
+mod aging;
+mod assign;
+mod edit;
+mod exemplar;
+mod history;
+mod index;
+mod store;
+
+use crate::{CosineScorer, Embedding, Prepared, Scorer};
+pub use aging::{Aging, AgingAction};
+pub use assign::{Assignment, OverflowPolicy};
+pub use edit::SpeakerRemap;
+pub use exemplar::{ExemplarOptions, ExemplarScoring};
+use eyre::{bail, ContextCompat, Result};
+pub use index::{HnswIndex, HnswOptions};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+/// Profiles scored exactly per lookup when an index is set, see
+/// [`EmbeddingManager::set_index`]
+pub const INDEX_CANDIDATES: usize = 32;
+
+/// How a speaker profile changes when new segments are matched to it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Adaptation {
+    /// Keep the first embedding of each speaker
+    #[default]
+    None,
+    /// Mean of all matched embeddings, weighted by segment duration
+    RunningMean,
+    /// Exponential moving average. `alpha` is the update rate for one second
+    /// of speech, longer segments move the profile further.
+    Ema(f32),
+}
+
+/// Speaker profile kept by [`EmbeddingManager`]
+#[derive(Debug, Clone)]
+pub struct Speaker {
+    embedding: Embedding,
+    weight: f32,
+    name: Option<String>,
+    /// Number in the `unknown-N` label, given to speakers created unnamed
+    unknown: Option<usize>,
+    exemplars: Vec<Embedding>,
+    /// Value of the manager clock when last assigned
+    last_seen: u64,
+    last_heard: f64,
+    speech_duration: f64,
+    /// Profile and exemplars prepared by the manager scorer, e.g. with
+    /// their cohort statistics. Reset whenever they change.
+    prepared: OnceLock<PreparedProfile>,
+}
+
+#[derive(Debug, Clone)]
+struct PreparedProfile {
+    embedding: Prepared,
+    exemplars: Vec<Prepared>,
+}
+
+impl Speaker {
+    fn new(embedding: Embedding, weight: f32) -> Self {
+        Self {
+            embedding,
+            weight,
+            name: None,
+            unknown: None,
+            exemplars: Vec::new(),
+            last_seen: 0,
+            last_heard: 0.0,
+            speech_duration: 0.0,
+            prepared: OnceLock::new(),
+        }
+    }
+
+    /// Name given at enrollment
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Current profile embedding
+    pub fn embedding(&self) -> &Embedding {
+        &self.embedding
+    }
+
+    /// Total weight (usually seconds of speech) merged into the profile
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Returns whether the profile embedding changed
+    fn adapt(&mut self, adaptation: Adaptation, embedding: &Embedding, weight: f32) -> bool {
+        let rate = match adaptation {
+            Adaptation::None => 0.0,
+            Adaptation::RunningMean => weight / (self.weight + weight),
+            Adaptation::Ema(alpha) => 1.0 - (1.0 - alpha.clamp(0.0, 1.0)).powf(weight),
+        };
+        self.weight += weight;
+        if rate > 0.0 && rate.is_finite() {
+            let current = self.embedding.as_array();
+            let updated = current + &((embedding.as_array() - current) * rate);
+            if let Ok(updated) = Embedding::from_array(updated) {
+                self.embedding = updated;
+                self.changed();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn changed(&mut self) {
+        self.prepared.take();
+    }
+
+    fn prepared(&self, scorer: &dyn Scorer) -> Result<&PreparedProfile> {
+        if let Some(prepared) = self.prepared.get() {
+            return Ok(prepared);
+        }
+        let prepared = PreparedProfile {
+            embedding: scorer.prepare(&self.embedding)?,
+            exemplars: self
+                .exemplars
+                .iter()
+                .map(|exemplar| scorer.prepare(exemplar))
+                .collect::<Result<_>>()?,
+        };
+        Ok(self.prepared.get_or_init(|| prepared))
+    }
+}
+
+/// Result of [`EmbeddingManager::identify`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identification {
+    /// `None` for voices reported as unknown by [`OverflowPolicy::Unknown`]
+    pub speaker_id: Option<usize>,
+    pub label: String,
+    /// Score against the speaker profile. For new speakers the best score
+    /// of the existing ones, `None` if there were none.
+    pub score: Option<f32>,
+    /// Whether the speaker was created for this embedding
+    pub new: bool,
+}
+
+/// Candidate speaker returned by [`EmbeddingManager::query`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeakerMatch {
+    pub speaker_id: usize,
+    pub score: f32,
+}
+
+/// Result of [`EmbeddingManager::query`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeakerQuery {
+    /// Best match first
+    pub matches: Vec<SpeakerMatch>,
+    /// Score difference between the best and second best speaker, `None`
+    /// with fewer than two speakers
+    pub margin: Option<f32>,
+}
+
+impl SpeakerQuery {
+    pub fn best(&self) -> Option<&SpeakerMatch> {
+        self.matches.first()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingManager {
+    max_speakers: usize,
+    speakers: HashMap<usize, Speaker>,
+    next_speaker_id: usize,
+    next_unknown: usize,
+    adaptation: Adaptation,
+    history: Option<Vec<history::Assigned>>,
+    index: Option<HnswIndex>,
+    exemplars: Option<ExemplarOptions>,
+    scorer: Arc<dyn Scorer>,
+    overflow: OverflowPolicy,
+    /// Number of assignments made, orders speakers by recency
+    clock: u64,
+    aging: Option<Aging>,
+    archived: HashMap<usize, Speaker>,
+    /// Stream time in seconds
+    now: f64,
+}
+
+impl EmbeddingManager {
+    pub fn new(max_speakers: usize) -> Self {
+        Self {
+            max_speakers,
+            speakers: HashMap::new(),
+            next_speaker_id: 1,
+            next_unknown: 1,
+            adaptation: Adaptation::None,
+            history: None,
+            index: None,
+            exemplars: None,
+            scorer: Arc::new(CosineScorer),
+            overflow: OverflowPolicy::Reject,
+            clock: 0,
+            aging: None,
+            archived: HashMap::new(),
+            now: 0.0,
+        }
+    }
+
+    /// Update speaker profiles as segments are matched to them
+    pub fn with_adaptation(mut self, adaptation: Adaptation) -> Self {
+        self.adaptation = adaptation;
+        self
+    }
+
+    /// Score speakers with another metric than cosine similarity, or
+    /// normalize scores with a [`crate::NormalizedScorer`]. Thresholds are on
+    /// the scale of the scorer. Fails with an index set if the scorer
+    /// doesn't rank like cosine similarity, see [`Scorer::ranks_by_cosine`].
+    pub fn with_scorer(mut self, scorer: Arc<dyn Scorer>) -> Result<Self> {
+        if self.index.is_some() && !scorer.ranks_by_cosine() {
+            bail!("The speaker index retrieves by cosine similarity, the scorer ranks differently")
+        }
+        self.scorer = scorer;
+        for speaker in self.speakers.values_mut().chain(self.archived.values_mut()) {
+            speaker.changed();
+        }
+        Ok(self)
+    }
+
+    pub fn scorer(&self) -> &Arc<dyn Scorer> {
+        &self.scorer
+    }
+
+    /// Look up speakers through an approximate nearest neighbor index
+    /// instead of scoring every profile, for galleries of thousands of
+    /// speakers. The [`INDEX_CANDIDATES`] speakers whose profile or an
+    /// exemplar is closest by cosine similarity are retrieved and scored.
+    /// `None` goes back to the linear scan. Fails if the scorer doesn't rank
+    /// like cosine similarity, see [`Scorer::ranks_by_cosine`].
+    pub fn set_index(&mut self, options: Option<HnswOptions>) -> Result<()> {
+        if options.is_some() && !self.scorer.ranks_by_cosine() {
+            bail!("The speaker index retrieves by cosine similarity, the scorer ranks differently")
+        }
+        self.index = match options {
+            Some(options) => Some(HnswIndex::new(options)?),
+            None => None,
+        };
+        let mut speaker_ids: Vec<usize> = self.speakers.keys().copied().collect();
+        speaker_ids.sort();
+        for speaker_id in speaker_ids {
+            self.sync_index(speaker_id)?;
+        }
+        Ok(())
+    }
+
+    /// Mirror the current profile and exemplars of a speaker in the index,
+    /// stored under its id in that order
+    fn sync_index(&mut self, speaker_id: usize) -> Result<()> {
+        let Some(index) = self.index.as_mut() else {
+            return Ok(());
+        };
+        match self.speakers.get(&speaker_id) {
+            Some(speaker) => {
+                let embeddings: Vec<Embedding> = std::iter::once(&speaker.embedding)
+                    .chain(&speaker.exemplars)
+                    .cloned()
+                    .collect();
+                index.insert_all(speaker_id, &embeddings)?;
+            }
+            None => {
+                index.remove(speaker_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_weight(weight: f32) -> Result<()> {
+        if !(weight >= 0.0 && weight.is_finite()) {
+            bail!("Invalid weight {}", weight)
+        }
+        Ok(())
+    }
+
+    fn check_dim(&self, embedding: &Embedding) -> Result<()> {
+        if let Some(speaker) = self.speakers.values().next() {
+            speaker.embedding.check_dim(embedding)?;
+        }
+        if let Some(dim) = self.scorer.input_dim() {
+            if dim != embedding.dim() {
+                bail!(
+                    "Embedding dimension mismatch: {} != {}",
+                    embedding.dim(),
+                    dim
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Search or create speaker
+    pub fn search_speaker(
+        &mut self,
+        embedding: &Embedding,
+        threshold: f32,
+    ) -> Result<Option<usize>> {
+        self.search_speaker_weighted(embedding, threshold, 1.0)
+    }
+
+    /// Search or create speaker. `weight` is how much the embedding counts
+    /// when adapting the matched profile, usually the segment duration.
+    pub fn search_speaker_weighted(
+        &mut self,
+        embedding: &Embedding,
+        threshold: f32,
+        weight: f32,
+    ) -> Result<Option<usize>> {
+        Ok(self.assign(embedding, threshold, weight)?.speaker_id())
+    }
+
+    /// Search or create speaker and return its label with the similarity
+    /// to its profile. Enrolled speakers are labeled with their name,
+    /// everyone else as `unknown-N`, and voices over capacity reported by
+    /// [`OverflowPolicy::Unknown`] as `unknown`. `None` if rejected.
+    pub fn identify(
+        &mut self,
+        embedding: &Embedding,
+        threshold: f32,
+    ) -> Result<Option<Identification>> {
+        let assignment = self.assign(embedding, threshold, 1.0)?;
+        if assignment == Assignment::Unknown {
+            return Ok(Some(Identification {
+                speaker_id: None,
+                label: "unknown".to_string(),
+                score: None,
+                new: false,
+            }));
+        }
+        Ok(assignment.speaker_id().map(|speaker_id| Identification {
+            speaker_id: Some(speaker_id),
+            label: self.label(speaker_id).unwrap_or_default(),
+            score: assignment.score(),
+            new: matches!(
+                assignment,
+                Assignment::Created { .. } | Assignment::Evicted { .. }
+            ),
+        }))
+    }
+
+    /// Enroll a named speaker from one or more embeddings, e.g. recorded
+    /// while the participant introduced themselves. Enrolling an existing name
+    /// again merges the new embeddings into its profile. Fails once
+    /// `max_speakers` speakers exist.
+    pub fn enroll(&mut self, name: &str, embeddings: &[Embedding]) -> Result<usize> {
+        let profile = Embedding::average(embeddings)?;
+        self.check_dim(&profile)?;
+        let weight = embeddings.len() as f32;
+
+        if let Some(speaker_id) = self.find_speaker(name) {
+            if let Some(speaker) = self.speakers.get_mut(&speaker_id) {
+                speaker.adapt(Adaptation::RunningMean, &profile, weight);
+            }
+            self.add_exemplars(speaker_id, embeddings)?;
+            self.sync_index(speaker_id)?;
+            return Ok(speaker_id);
+        }
+
+        if self.speakers.len() >= self.max_speakers {
+            bail!(
+                "Can't enroll {}, there are already {} speakers",
+                name,
+                self.max_speakers
+            )
+        }
+        let speaker_id = self.add_speaker(profile, weight, Some(name))?;
+        self.add_exemplars(speaker_id, embeddings)?;
+        Ok(speaker_id)
+    }
+
+    /// Id of the speaker enrolled as `name`
+    pub fn find_speaker(&self, name: &str) -> Option<usize> {
+        self.speakers
+            .iter()
+            .find(|(_, speaker)| speaker.name.as_deref() == Some(name))
+            .map(|(&speaker_id, _)| speaker_id)
+    }
+
+    /// Name of an enrolled speaker, `unknown-N` otherwise
+    pub fn label(&self, speaker_id: usize) -> Option<String> {
+        let speaker = self.speakers.get(&speaker_id)?;
+        Some(match &speaker.name {
+            Some(name) => name.clone(),
+            None => format!("unknown-{}", speaker.unknown.unwrap_or(speaker_id)),
+        })
+    }
+
+    pub fn get_best_speaker_match(&mut self, embedding: &Embedding) -> Result<usize> {
+        if self.speakers.is_empty() {
+            bail!("no speakers")
+        }
+        self.check_dim(embedding)?;
+        Ok(self
+            .best_match(embedding)?
+            .map_or(0, |(speaker_id, _)| speaker_id))
+    }
+
+    /// Top `k` speakers most similar to `embedding`, without creating or
+    /// updating any speaker
+    pub fn query(&self, embedding: &Embedding, k: usize) -> Result<SpeakerQuery> {
+        self.check_dim(embedding)?;
+        let mut matches: Vec<SpeakerMatch> = self
+            .scores(embedding, k)?
+            .into_iter()
+            .map(|(speaker_id, score)| SpeakerMatch { speaker_id, score })
+            .collect();
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.speaker_id.cmp(&b.speaker_id))
+        });
+        let margin = match matches.as_slice() {
+            [best, second, ..] => Some(best.score - second.score),
+            _ => None,
+        };
+        matches.truncate(k);
+        Ok(SpeakerQuery { matches, margin })
+    }
+
+    /// Similarity of `embedding` to every speaker, or to the closest
+    /// candidates when indexed. Uses the exemplars when kept.
+    fn scores(&self, embedding: &Embedding, k: usize) -> Result<Vec<(usize, f32)>> {
+        let candidates: Vec<(usize, &Speaker)> = match &self.index {
+            Some(index) => index
+                .search(embedding, k.max(INDEX_CANDIDATES))?
+                .into_iter()
+                .filter_map(|m| Some((m.speaker_id, self.speakers.get(&m.speaker_id)?)))
+                .collect(),
+            None => self
+                .speakers
+                .iter()
+                .map(|(&speaker_id, speaker)| (speaker_id, speaker))
+                .collect(),
+        };
+        let scorer = self.scorer.as_ref();
+        let query = scorer.prepare(embedding)?;
+        candidates
+            .into_iter()
+            .map(|(speaker_id, speaker)| {
+                let profile = speaker.prepared(scorer)?;
+                let exemplar_score = match self.exemplars {
+                    Some(options) => exemplar::exemplar_score(
+                        scorer,
+                        &query,
+                        &profile.exemplars,
+                        options.scoring,
+                    )?,
+                    None => None,
+                };
+                let score = match exemplar_score {
+                    Some(score) => score,
+                    None => scorer.score_prepared(&query, &profile.embedding)?,
+                };
+                Ok((speaker_id, score))
+            })
+            .collect()
+    }
+
+    /// Most similar speaker and its score
+    fn best_match(&self, embedding: &Embedding) -> Result<Option<(usize, f32)>> {
+        let mut best = None;
+        for (speaker_id, similarity) in self.scores(embedding, 1)? {
+            if best.is_none_or(|(_, best_similarity)| similarity > best_similarity) {
+                best = Some((speaker_id, similarity));
+            }
+        }
+        Ok(best)
+    }
+
+    /// Adapt a speaker profile with a new embedding according to the
+    /// configured [`Adaptation`]
+    pub fn update_speaker(
+        &mut self,
+        speaker_id: usize,
+        embedding: &Embedding,
+        weight: f32,
+    ) -> Result<()> {
+        Self::check_weight(weight)?;
+        self.check_dim(embedding)?;
+        let adaptation = self.adaptation;
+        let speaker = self
+            .speakers
+            .get_mut(&speaker_id)
+            .with_context(|| format!("Unknown speaker {}", speaker_id))?;
+        if speaker.adapt(adaptation, embedding, weight) {
+            if let Some(index) = self.index.as_mut() {
+                index.replace(speaker_id, 0, &speaker.embedding)?;
+            }
+        }
+        self.add_exemplars(speaker_id, std::slice::from_ref(embedding))
+    }
+
+    fn add_speaker(
+        &mut self,
+        embedding: Embedding,
+        weight: f32,
+        name: Option<&str>,
+    ) -> Result<usize> {
+        let speaker_id = self.next_speaker_id;
+        let mut speaker = Speaker::new(embedding, weight);
+        speaker.name = name.map(str::to_string);
+        if name.is_none() {
+            speaker.unknown = Some(self.next_unknown);
+            self.next_unknown += 1;
+        }
+        speaker.last_seen = self.clock;
+        speaker.last_heard = self.now;
+        self.speakers.insert(speaker_id, speaker);
+        self.next_speaker_id += 1;
+        self.sync_index(speaker_id)?;
+        Ok(speaker_id)
+    }
+
+    #[allow(unused)]
+    pub fn get_all_speakers(&self) -> &HashMap<usize, Speaker> {
+        &self.speakers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::{Linkage, Stop};
+
+    fn embedding(values: &[f32]) -> Embedding {
+        Embedding::new(values.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn running_mean_is_weighted_by_duration() {
+        let mut manager = EmbeddingManager::new(2).with_adaptation(Adaptation::RunningMean);
+        let id = manager
+            .search_speaker_weighted(&embedding(&[1.0, 0.0]), 0.5, 1.0)
+            .unwrap()
+            .unwrap();
+        let matched = manager
+            .search_speaker_weighted(&embedding(&[1.0, 1.0]), 0.5, 3.0)
+            .unwrap();
+        assert_eq!(matched, Some(id));
+
+        let speaker = &manager.get_all_speakers()[&id];
+        assert_eq!(speaker.weight(), 4.0);
+        assert_eq!(speaker.embedding().as_slice(), &[1.0, 0.75]);
+
+        for weight in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(manager
+                .search_speaker_weighted(&embedding(&[1.0, 0.0]), 0.5, weight)
+                .is_err());
+            assert!(manager
+                .update_speaker(id, &embedding(&[1.0, 0.0]), weight)
+                .is_err());
+        }
+        assert_eq!(manager.get_all_speakers()[&id].weight(), 4.0);
+    }
+
+    #[test]
+    fn no_adaptation_keeps_first_embedding() {
+        let mut manager = EmbeddingManager::new(2);
+        let id = manager
+            .search_speaker(&embedding(&[1.0, 0.0]), 0.5)
+            .unwrap()
+            .unwrap();
+        manager
+            .search_speaker(&embedding(&[1.0, 0.5]), 0.5)
+            .unwrap();
+        assert_eq!(
+            manager.get_all_speakers()[&id].embedding().as_slice(),
+            &[1.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn identifies_enrolled_speakers() {
+        let mut manager = EmbeddingManager::new(3);
+        manager
+            .enroll("alice", &[embedding(&[1.0, 0.0]), embedding(&[0.9, 0.1])])
+            .unwrap();
+        manager.enroll("bob", &[embedding(&[-1.0, 0.0])]).unwrap();
+
+        let alice = manager
+            .identify(&embedding(&[1.0, 0.05]), 0.5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.label, "alice");
+        assert!(alice.score.unwrap() > 0.9);
+        assert!(!alice.new);
+
+        let other = manager
+            .identify(&embedding(&[0.0, 1.0]), 0.5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(other.label, "unknown-1");
+        assert!(other.new);
+        assert!(other.score.unwrap() < 0.5);
+
+        // Full
+        assert!(manager.enroll("carol", &[embedding(&[0.0, -1.0])]).is_err());
+        let mut manager = manager.with_overflow(OverflowPolicy::Unknown);
+        let unknown = manager
+            .identify(&embedding(&[0.0, -1.0]), 0.5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(unknown.speaker_id, None);
+        assert_eq!(unknown.label, "unknown");
+    }
+
+    #[test]
+    fn query_ranks_speakers() {
+        let mut manager = EmbeddingManager::new(usize::MAX);
+        manager.enroll("a", &[embedding(&[1.0, 0.0])]).unwrap();
+        manager.enroll("b", &[embedding(&[0.0, 1.0])]).unwrap();
+        manager.enroll("c", &[embedding(&[-1.0, 0.0])]).unwrap();
+
+        let query = manager.query(&embedding(&[1.0, 0.2]), 2).unwrap();
+        let ids: Vec<usize> = query.matches.iter().map(|m| m.speaker_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        let margin = query.matches[0].score - query.matches[1].score;
+        assert_eq!(query.margin, Some(margin));
+
+        manager.set_index(Some(HnswOptions::default())).unwrap();
+        assert_eq!(manager.query(&embedding(&[1.0, 0.2]), 2).unwrap(), query);
+    }
+
+    #[test]
+    fn merge_and_remove_remap_ids() {
+        let mut manager = EmbeddingManager::new(usize::MAX);
+        let a = manager.enroll("a", &[embedding(&[1.0, 0.0])]).unwrap();
+        let b = manager
+            .search_speaker(&embedding(&[0.0, 1.0]), 0.5)
+            .unwrap()
+            .unwrap();
+        let c = manager
+            .search_speaker(&embedding(&[-1.0, 0.0]), 0.5)
+            .unwrap()
+            .unwrap();
+
+        let remap = manager
+            .merge(b, a)
+            .unwrap()
+            .and_then(&manager.remove(c).unwrap());
+        assert_eq!(remap.apply(a), Some(b));
+        assert_eq!(remap.apply(b), Some(b));
+        assert_eq!(remap.apply(c), None);
+        assert_eq!(manager.label(b).as_deref(), Some("a"));
+        assert_eq!(manager.get_all_speakers()[&b].weight(), 2.0);
+
+        manager.relabel(b, Some("bob")).unwrap();
+        assert_eq!(manager.find_speaker("bob"), Some(b));
+        let (split, remap) = manager.split(b, &[(embedding(&[1.0, 0.0]), 1.0)]).unwrap();
+        assert!(remap.is_empty());
+        assert!(manager.relabel(split, Some("bob")).is_err());
+        // The moved speech no longer counts for bob
+        let bob = &manager.get_all_speakers()[&b];
+        assert_eq!(bob.weight(), 1.0);
+        assert_eq!(bob.embedding().as_slice(), &[0.0, 1.0]);
+
+        // Moving everything replaces the speaker
+        let (moved, remap) = manager.split(b, &[(embedding(&[0.0, 1.0]), 1.0)]).unwrap();
+        assert_eq!(remap.apply(b), Some(moved));
+        assert!(!manager.get_all_speakers().contains_key(&b));
+
+        // Segments move out by their duration
+        let mut manager = EmbeddingManager::new(4).with_adaptation(Adaptation::RunningMean);
+        let (x, y) = (embedding(&[1.0, 0.0]), embedding(&[0.0, 1.0]));
+        let a = manager
+            .search_speaker_weighted(&x, -1.0, 6.5)
+            .unwrap()
+            .unwrap();
+        manager.search_speaker_weighted(&y, -1.0, 6.0).unwrap();
+        let (split, _) = manager.split(a, &[(y.clone(), 6.0)]).unwrap();
+        let speakers = manager.get_all_speakers();
+        assert_eq!(speakers[&a].weight(), 6.5);
+        assert!(speakers[&a].embedding().euclidean(&x) < 1e-5);
+        assert_eq!(speakers[&split].weight(), 6.0);
+        assert_eq!(speakers[&split].embedding(), &y);
+        assert!(manager.split(a, &[(x, f32::NAN)]).is_err());
+    }
+
+    #[test]
+    fn finalize_corrects_online_labels() {
+        let values = [[1.0, 0.0], [0.9, 0.1], [0.0, 1.0], [1.0, 0.1], [0.1, 0.9]];
+        let online = |max_speakers| {
+            let mut manager = EmbeddingManager::new(max_speakers).with_history();
+            for v in values {
+                // A loose threshold puts everyone on the first speaker
+                manager.search_speaker(&embedding(&v), -1.0).unwrap();
+            }
+            assert_eq!(manager.get_all_speakers().len(), 1);
+            manager
+        };
+
+        let mut manager = online(usize::MAX);
+        let stop = Stop::Threshold(0.3);
+        let labels = manager.finalize(Linkage::Average, stop).unwrap();
+        assert_eq!(labels, vec![1, 1, 2, 1, 2]);
+        assert_eq!(manager.get_all_speakers().len(), 2);
+        assert_eq!(manager.finalize(Linkage::Average, stop).unwrap(), labels);
+        assert_eq!(manager.get_all_speakers().len(), 2);
+
+        // No room for a second speaker
+        let mut manager = online(1);
+        let labels = manager.finalize(Linkage::Average, stop).unwrap();
+        assert_eq!(labels, vec![1; 5]);
+        assert_eq!(manager.get_all_speakers().len(), 1);
+    }
+
+    #[test]
+    fn exemplars_cover_varied_speech() {
+        let mut manager = EmbeddingManager::new(usize::MAX).with_exemplars(ExemplarOptions {
+            max_exemplars: 2,
+            scoring: ExemplarScoring::Max,
+        });
+        // Same person on a headset and on a phone line
+        let phone = embedding(&[0.0, 1.0]);
+        manager
+            .enroll("a", &[embedding(&[1.0, 0.0]), phone.clone(), phone.clone()])
+            .unwrap();
+        let speaker = &manager.get_all_speakers()[&1];
+        assert_eq!(speaker.exemplars(), &[embedding(&[1.0, 0.0]), phone]);
+
+        let identified = manager
+            .identify(&embedding(&[0.05, 1.0]), 0.9)
+            .unwrap()
+            .unwrap();
+        assert_eq!(identified.label, "a");
+        assert!(identified.score.unwrap() > 0.99);
+    }
+
+    #[test]
+    fn index_follows_online_updates() {
+        let mut manager = EmbeddingManager::new(usize::MAX)
+            .with_adaptation(Adaptation::RunningMean)
+            .with_exemplars(ExemplarOptions {
+                max_exemplars: 3,
+                scoring: ExemplarScoring::Max,
+            });
+        manager.set_index(Some(HnswOptions::default())).unwrap();
+        for i in 0..40 {
+            let angle = (i % 8) as f32 * 0.05 + if i % 2 == 0 { 0.0 } else { 1.5 };
+            let values = [angle.cos(), angle.sin(), 0.1 * (i % 3) as f32];
+            manager.assign(&embedding(&values), 0.7, 1.0).unwrap();
+        }
+
+        // Every profile and exemplar is found, nothing else is left behind
+        let index = manager.index.as_ref().unwrap();
+        assert_eq!(index.len(), manager.speakers.len());
+        for (&speaker_id, speaker) in &manager.speakers {
+            for embedding in std::iter::once(&speaker.embedding).chain(&speaker.exemplars) {
+                let found = index.search(embedding, 1).unwrap();
+                assert_eq!(found[0].speaker_id, speaker_id);
+                assert!(found[0].score > 0.999);
+            }
+        }
+        let mut rebuilt = manager.clone();
+        rebuilt.set_index(Some(HnswOptions::default())).unwrap();
+        let query = embedding(&[1.0, 0.2, 0.0]);
+        assert_eq!(
+            manager.query(&query, 2).unwrap(),
+            rebuilt.query(&query, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn index_retrieves_speakers_by_exemplar() {
+        let mut manager = EmbeddingManager::new(usize::MAX).with_exemplars(ExemplarOptions {
+            max_exemplars: 2,
+            scoring: ExemplarScoring::Max,
+        });
+        let headset = embedding(&[1.0, 0.0, 0.0]);
+        let phone = embedding(&[0.0, 0.0, 1.0]);
+        manager
+            .enroll("a", &[headset.clone(), headset.clone(), headset, phone])
+            .unwrap();
+        // Closer to a phone call than the profile of a, but not the same
+        for i in 0..2 * INDEX_CANDIDATES {
+            let name = format!("other-{}", i);
+            let values = [0.01 * i as f32, 1.0, 0.8];
+            manager.enroll(&name, &[embedding(&values)]).unwrap();
+        }
+        manager.set_index(Some(HnswOptions::default())).unwrap();
+
+        let query = manager.query(&embedding(&[0.02, 0.0, 1.0]), 1).unwrap();
+        assert_eq!(query.best().map(|m| m.speaker_id), Some(1));
+    }
+
+    #[test]
+    fn custom_scorer_sets_threshold_scale() {
+        let mut manager = EmbeddingManager::new(usize::MAX)
+            .with_scorer(Arc::new(crate::EuclideanScorer))
+            .unwrap();
+        let near = manager
+            .search_speaker(&embedding(&[1.0, 0.0]), -0.5)
+            .unwrap();
+        // Same direction but far away, a new speaker by distance
+        let far = manager
+            .search_speaker(&embedding(&[3.0, 0.0]), -0.5)
+            .unwrap();
+        assert_ne!(near, far);
+        let query = manager.query(&embedding(&[2.9, 0.0]), 1).unwrap();
+        assert_eq!(query.best().map(|m| m.speaker_id), far);
+
+        // The index retrieves by cosine similarity
+        assert!(manager.set_index(Some(HnswOptions::default())).is_err());
+        let mut manager = EmbeddingManager::new(usize::MAX);
+        manager.set_index(Some(HnswOptions::default())).unwrap();
+        assert!(manager
+            .with_scorer(Arc::new(crate::EuclideanScorer))
+            .is_err());
+    }
+
+    #[test]
+    fn normalized_scores_cache_profile_stats() {
+        #[derive(Debug, Default)]
+        struct Counting(std::sync::atomic::AtomicUsize);
+        impl Scorer for Counting {
+            fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                a.cosine_similarity(b)
+            }
+        }
+
+        let counting = Arc::new(Counting::default());
+        let impostors = [[1.0, 1.0], [-1.0, 1.0], [1.0, -1.0], [-1.0, -1.0]];
+        let cohort = crate::Cohort::new(impostors.map(|v| embedding(&v)).to_vec(), 2).unwrap();
+        let scorer = crate::NormalizedScorer::new(counting.clone(), cohort).unwrap();
+        let mut manager = EmbeddingManager::new(usize::MAX)
+            .with_scorer(Arc::new(scorer))
+            .unwrap();
+        manager.enroll("a", &[embedding(&[1.0, 0.0])]).unwrap();
+        manager.enroll("b", &[embedding(&[0.0, 1.0])]).unwrap();
+
+        let calls = || counting.0.load(std::sync::atomic::Ordering::Relaxed);
+        let query = manager.query(&embedding(&[1.0, 0.1]), 2).unwrap();
+        assert_eq!(query.best().map(|m| m.speaker_id), Some(1));
+        // Cohort statistics of the query and both profiles, then the pairs
+        assert_eq!(calls(), 3 * 4 + 2);
+        manager.query(&embedding(&[1.0, 0.1]), 2).unwrap();
+        assert_eq!(calls(), 3 * 4 + 2 + 4 + 2);
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let a = embedding(&[1.0, 0.0]);
+        let b = embedding(&[0.0, 1.0]);
+        let c = embedding(&[-1.0, 0.2]);
+        let full = |overflow| {
+            let mut manager = EmbeddingManager::new(2).with_overflow(overflow);
+            // Recency doesn't depend on the weights
+            manager.assign(&a, 0.5, 0.0).unwrap();
+            manager.assign(&b, 0.5, 0.0).unwrap();
+            manager.assign(&a, 0.5, 0.0).unwrap();
+            manager
+        };
+
+        let mut manager = full(OverflowPolicy::Reject);
+        assert_eq!(manager.assign(&c, 0.5, 1.0).unwrap(), Assignment::Rejected);
+        let mut manager = full(OverflowPolicy::Unknown);
+        assert_eq!(manager.assign(&c, 0.5, 1.0).unwrap(), Assignment::Unknown);
+        let mut manager = full(OverflowPolicy::Nearest);
+        let nearest = manager.assign(&c, 0.5, 1.0).unwrap();
+        assert!(matches!(nearest, Assignment::Nearest { speaker_id: 2, .. }));
+        assert_eq!(manager.get_all_speakers()[&2].embedding(), &b);
+
+        // Speaker 2 was seen before speaker 1 matched again
+        let mut manager = full(OverflowPolicy::EvictLeastRecent);
+        let evicted = manager.assign(&c, 0.5, 1.0).unwrap();
+        let Assignment::Evicted {
+            speaker_id: 3,
+            evicted: 2,
+            remap,
+            ..
+        } = &evicted
+        else {
+            panic!("expected an eviction, got {:?}", evicted)
+        };
+        assert_eq!(remap.apply(2), None);
+        // Only segments with stream times move the stream clock
+        assert_eq!(manager.now(), 0.0);
+        // Best score of the speakers before eviction
+        assert!((evicted.score().unwrap() - b.cosine(&c)).abs() < 1e-6);
+        assert_eq!(manager.get_all_speakers().len(), 2);
+    }
+
+    #[test]
+    fn aging_archives_silent_speakers() {
+        let mut manager = EmbeddingManager::new(usize::MAX)
+            .with_aging(Aging {
+                horizon: 60.0,
+                action: AgingAction::Archive,
+            })
+            .unwrap();
+        let (a, b) = (embedding(&[1.0, 0.0]), embedding(&[0.0, 1.0]));
+        manager.assign_segment(&a, 0.5, 0.0, 5.0).unwrap();
+        manager.assign_segment(&b, 0.5, 10.0, 15.0).unwrap();
+        manager.assign_segment(&b, 0.5, 70.0, 75.0).unwrap();
+
+        assert_eq!(manager.archived().keys().collect::<Vec<_>>(), vec![&1]);
+        let speaker = &manager.get_all_speakers()[&2];
+        assert_eq!(speaker.speech_duration(), 10.0);
+        assert_eq!(speaker.last_heard(), 75.0);
+
+        // Archived profiles don't match anymore
+        let assignment = manager.assign_segment(&a, 0.5, 80.0, 81.0).unwrap();
+        assert!(matches!(
+            assignment,
+            Assignment::Created { speaker_id: 3, .. }
+        ));
+        manager.restore(1).unwrap();
+        assert_eq!(manager.get_all_speakers().len(), 3);
+
+        for horizon in [-1.0, f64::NAN, f64::INFINITY] {
+            let aging = Aging {
+                horizon,
+                action: AgingAction::Expire,
+            };
+            assert!(EmbeddingManager::new(1).with_aging(aging).is_err());
+        }
+    }
+
+    #[test]
+    fn aging_prunes_history() {
+        let mut manager = EmbeddingManager::new(usize::MAX)
+            .with_history()
+            .with_aging(Aging {
+                horizon: 60.0,
+                action: AgingAction::Expire,
+            })
+            .unwrap();
+        let (a, b) = (embedding(&[1.0, 0.0]), embedding(&[0.0, 1.0]));
+        manager.assign_segment(&a, 0.5, 0.0, 5.0).unwrap();
+        manager.assign_segment(&b, 0.5, 10.0, 15.0).unwrap();
+        manager.assign_segment(&b, 0.5, 70.0, 75.0).unwrap();
+
+        // The expired speaker keeps its label and isn't brought back
+        let labels = manager
+            .finalize(Linkage::Average, Stop::Threshold(0.5))
+            .unwrap();
+        assert_eq!(labels, vec![1, 2, 2]);
+        assert_eq!(manager.get_all_speakers().len(), 1);
+    }
+}
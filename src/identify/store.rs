@@ -0,0 +1,466 @@
+//! On-disk format of [`EmbeddingManager`] state.
+//!
+//! Little endian binary: magic, format version, model id, manager settings,
+//! then every speaker with its id, weight, optional name, embedding,
+//! exemplars, activity and `unknown-N` label number.
+
+use super::{
+    Adaptation, Aging, AgingAction, EmbeddingManager, ExemplarOptions, ExemplarScoring,
+    OverflowPolicy, Speaker,
+};
+use crate::Embedding;
+use eyre::{bail, Context, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"PYSPKDB\0";
+const VERSION: u32 = 1;
+
+impl EmbeddingManager {
+    /// Save speakers to `path`. `model_id` identifies the embedding model
+    /// the profiles were computed with, see [`crate::EmbeddingExtractor::model_id`].
+    pub fn save<P: AsRef<Path>>(&self, path: P, model_id: &str) -> Result<()> {
+        let path = path.as_ref();
+        // Write next to the target and rename, so a crash never leaves a
+        // truncated database behind
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, VERSION)?;
+        write_str(&mut writer, model_id)?;
+        write_u64(&mut writer, self.max_speakers as u64)?;
+        write_u64(&mut writer, self.next_speaker_id as u64)?;
+        match self.adaptation {
+            Adaptation::None => write_adaptation(&mut writer, 0, 0.0)?,
+            Adaptation::RunningMean => write_adaptation(&mut writer, 1, 0.0)?,
+            Adaptation::Ema(alpha) => write_adaptation(&mut writer, 2, alpha)?,
+        }
+        // Zero exemplars per speaker means exemplars are off
+        let (max_exemplars, scoring) =
+            self.exemplars.map_or((0, ExemplarScoring::Max), |options| {
+                (options.max_exemplars, options.scoring)
+            });
+        write_u64(&mut writer, max_exemplars as u64)?;
+        match scoring {
+            ExemplarScoring::Max => write_scoring(&mut writer, 0, 0)?,
+            ExemplarScoring::Mean => write_scoring(&mut writer, 1, 0)?,
+            ExemplarScoring::TopMean(n) => write_scoring(&mut writer, 2, n)?,
+        }
+        writer.write_all(&[match self.overflow {
+            OverflowPolicy::Reject => 0,
+            OverflowPolicy::Nearest => 1,
+            OverflowPolicy::Unknown => 2,
+            OverflowPolicy::EvictLeastRecent => 3,
+        }])?;
+        write_u64(&mut writer, self.clock)?;
+        write_f64(&mut writer, self.now)?;
+        match self.aging {
+            None => write_aging(&mut writer, 0, 0.0)?,
+            Some(Aging {
+                horizon,
+                action: AgingAction::Expire,
+            }) => write_aging(&mut writer, 1, horizon)?,
+            Some(Aging {
+                horizon,
+                action: AgingAction::Archive,
+            }) => write_aging(&mut writer, 2, horizon)?,
+        }
+        write_u64(&mut writer, self.next_unknown as u64)?;
+
+        let mut speakers: Vec<(&usize, &Speaker, bool)> = self
+            .speakers
+            .iter()
+            .map(|(speaker_id, speaker)| (speaker_id, speaker, false))
+            .chain(
+                self.archived
+                    .iter()
+                    .map(|(speaker_id, speaker)| (speaker_id, speaker, true)),
+            )
+            .collect();
+        speakers.sort_by_key(|(speaker_id, _, _)| **speaker_id);
+        write_u64(&mut writer, speakers.len() as u64)?;
+        for (speaker_id, speaker, archived) in speakers {
+            write_u64(&mut writer, *speaker_id as u64)?;
+            write_f32(&mut writer, speaker.weight)?;
+            write_str(&mut writer, speaker.name.as_deref().unwrap_or_default())?;
+            write_embedding(&mut writer, &speaker.embedding)?;
+            write_u32(&mut writer, speaker.exemplars.len() as u32)?;
+            for exemplar in &speaker.exemplars {
+                write_embedding(&mut writer, exemplar)?;
+            }
+            write_u64(&mut writer, speaker.last_seen)?;
+            write_f64(&mut writer, speaker.last_heard)?;
+            write_f64(&mut writer, speaker.speech_duration)?;
+            writer.write_all(&[archived as u8])?;
+            // Zero for speakers created with a name
+            write_u64(&mut writer, speaker.unknown.unwrap_or_default() as u64)?;
+        }
+
+        writer.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Load speakers saved with [`EmbeddingManager::save`]. Fails if they were
+    /// computed with a different embedding model than `model_id`.
+    pub fn load<P: AsRef<Path>>(path: P, model_id: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // Lengths in the file are checked against the bytes left
+        let mut reader = bytes.as_slice();
+
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("Not a speaker database")?;
+        if &magic != MAGIC {
+            bail!("{} is not a speaker database", path.display())
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("Unsupported speaker database version {}", version)
+        }
+        let saved_model_id = read_str(&mut reader)?;
+        if saved_model_id != model_id {
+            bail!(
+                "Speaker database was created with model {} but {} is loaded",
+                saved_model_id,
+                model_id
+            )
+        }
+
+        let max_speakers = read_usize(&mut reader)?;
+        let next_speaker_id = read_usize(&mut reader)?;
+        let tag = read_u8(&mut reader)?;
+        let alpha = read_f32(&mut reader)?;
+        let adaptation = match tag {
+            0 => Adaptation::None,
+            1 => Adaptation::RunningMean,
+            2 => Adaptation::Ema(alpha),
+            _ => bail!("Unknown adaptation {}", tag),
+        };
+
+        let mut manager = EmbeddingManager::new(max_speakers).with_adaptation(adaptation);
+        let max_exemplars = read_usize(&mut reader)?;
+        let tag = read_u8(&mut reader)?;
+        let n = read_usize(&mut reader)?;
+        let scoring = match tag {
+            0 => ExemplarScoring::Max,
+            1 => ExemplarScoring::Mean,
+            2 => ExemplarScoring::TopMean(n),
+            _ => bail!("Unknown exemplar scoring {}", tag),
+        };
+        if max_exemplars > 0 {
+            manager = manager.with_exemplars(ExemplarOptions {
+                max_exemplars,
+                scoring,
+            });
+        }
+        manager.overflow = match read_u8(&mut reader)? {
+            0 => OverflowPolicy::Reject,
+            1 => OverflowPolicy::Nearest,
+            2 => OverflowPolicy::Unknown,
+            3 => OverflowPolicy::EvictLeastRecent,
+            tag => bail!("Unknown overflow policy {}", tag),
+        };
+        manager.clock = read_u64(&mut reader)?;
+        manager.now = read_finite(&mut reader, "stream time")?;
+        let tag = read_u8(&mut reader)?;
+        let horizon = read_f64(&mut reader)?;
+        manager.aging = match tag {
+            0 => None,
+            1 => Some(Aging {
+                horizon,
+                action: AgingAction::Expire,
+            }),
+            2 => Some(Aging {
+                horizon,
+                action: AgingAction::Archive,
+            }),
+            _ => bail!("Unknown aging {}", tag),
+        };
+        if let Some(aging) = manager.aging {
+            aging.check()?;
+        }
+        manager.next_speaker_id = next_speaker_id;
+        manager.next_unknown = read_usize(&mut reader)?;
+        for _ in 0..read_u64(&mut reader)? {
+            let speaker_id = read_usize(&mut reader)?;
+            let weight = read_f32(&mut reader)?;
+            EmbeddingManager::check_weight(weight)?;
+            let name = read_str(&mut reader)?;
+            let embedding = read_embedding(&mut reader)?;
+            manager.check_dim(&embedding)?;
+            if speaker_id >= next_speaker_id {
+                bail!("Speaker id {} is out of range", speaker_id)
+            }
+            if manager.speakers.contains_key(&speaker_id)
+                || manager.archived.contains_key(&speaker_id)
+            {
+                bail!("Speaker id {} is duplicated", speaker_id)
+            }
+
+            let mut speaker = Speaker::new(embedding, weight);
+            speaker.name = Some(name).filter(|name| !name.is_empty());
+            for _ in 0..read_u32(&mut reader)? {
+                let exemplar = read_embedding(&mut reader)?;
+                speaker.embedding.check_dim(&exemplar)?;
+                speaker.exemplars.push(exemplar);
+            }
+            speaker.last_seen = read_u64(&mut reader)?;
+            speaker.last_heard = read_finite(&mut reader, "last heard time")?;
+            speaker.speech_duration = read_finite(&mut reader, "speech duration")?;
+            let archived = read_u8(&mut reader)? != 0;
+            speaker.unknown = Some(read_usize(&mut reader)?).filter(|&unknown| unknown > 0);
+            if archived {
+                manager.archived.insert(speaker_id, speaker);
+            } else {
+                if manager.speakers.len() >= max_speakers {
+                    bail!("Speaker database holds more than {} speakers", max_speakers)
+                }
+                manager.speakers.insert(speaker_id, speaker);
+            }
+        }
+        Ok(manager)
+    }
+}
+
+fn write_adaptation(writer: &mut impl Write, tag: u8, alpha: f32) -> Result<()> {
+    writer.write_all(&[tag])?;
+    write_f32(writer, alpha)
+}
+
+fn write_scoring(writer: &mut impl Write, tag: u8, n: usize) -> Result<()> {
+    writer.write_all(&[tag])?;
+    write_u64(writer, n as u64)
+}
+
+fn write_aging(writer: &mut impl Write, tag: u8, horizon: f64) -> Result<()> {
+    writer.write_all(&[tag])?;
+    write_f64(writer, horizon)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> Result<()> {
+    Ok(writer.write_all(&value.to_le_bytes())?)
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> Result<()> {
+    Ok(writer.write_all(&value.to_le_bytes())?)
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> Result<()> {
+    Ok(writer.write_all(&value.to_le_bytes())?)
+}
+
+fn write_f64(writer: &mut impl Write, value: f64) -> Result<()> {
+    Ok(writer.write_all(&value.to_le_bytes())?)
+}
+
+fn write_str(writer: &mut impl Write, value: &str) -> Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    Ok(writer.write_all(value.as_bytes())?)
+}
+
+fn write_embedding(writer: &mut impl Write, embedding: &Embedding) -> Result<()> {
+    write_u32(writer, embedding.dim() as u32)?;
+    for value in embedding.as_array() {
+        write_f32(writer, *value)?;
+    }
+    Ok(())
+}
+
+/// Fail before allocating for a length that can't fit in the file
+fn check_remaining(reader: &[u8], len: usize) -> Result<()> {
+    if len > reader.len() {
+        bail!("Speaker database is truncated")
+    }
+    Ok(())
+}
+
+fn read_bytes<const N: usize>(reader: &mut &[u8]) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader
+        .read_exact(&mut bytes)
+        .context("Speaker database is truncated")?;
+    Ok(bytes)
+}
+
+fn read_u8(reader: &mut &[u8]) -> Result<u8> {
+    Ok(read_bytes::<1>(reader)?[0])
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_usize(reader: &mut &[u8]) -> Result<usize> {
+    Ok(usize::try_from(read_u64(reader)?)?)
+}
+
+fn read_f32(reader: &mut &[u8]) -> Result<f32> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f64(reader: &mut &[u8]) -> Result<f64> {
+    Ok(f64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_finite(reader: &mut &[u8], what: &str) -> Result<f64> {
+    let value = read_f64(reader)?;
+    if !value.is_finite() {
+        bail!("Invalid {} {} in speaker database", what, value)
+    }
+    Ok(value)
+}
+
+fn read_str(reader: &mut &[u8]) -> Result<String> {
+    let len = read_u32(reader)? as usize;
+    check_remaining(reader, len)?;
+    let mut bytes = vec![0; len];
+    reader
+        .read_exact(&mut bytes)
+        .context("Speaker database is truncated")?;
+    String::from_utf8(bytes).context("Invalid string in speaker database")
+}
+
+fn read_embedding(reader: &mut &[u8]) -> Result<Embedding> {
+    let dim = read_u32(reader)? as usize;
+    check_remaining(reader, dim.saturating_mul(4))?;
+    let values = (0..dim)
+        .map(|_| read_f32(reader))
+        .collect::<Result<Vec<_>>>()?;
+    Embedding::new(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(values: &[f32]) -> Embedding {
+        Embedding::new(values.to_vec()).unwrap()
+    }
+
+    /// Per test and process, so concurrent runs don't share files
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pyannote-rs-{}-{}.db", name, std::process::id()))
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let path = temp_path("save_and_load_roundtrip");
+        let mut manager = EmbeddingManager::new(8).with_adaptation(Adaptation::Ema(0.1));
+        manager.enroll("alice", &[embedding(&[1.0, 0.0])]).unwrap();
+        manager
+            .search_speaker(&embedding(&[0.0, 1.0]), 0.5)
+            .unwrap();
+        manager.save(&path, "model-a").unwrap();
+
+        assert!(EmbeddingManager::load(&path, "model-b").is_err());
+        let mut loaded = EmbeddingManager::load(&path, "model-a").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.get_all_speakers().len(), 2);
+        assert_eq!(loaded.find_speaker("alice"), Some(1));
+        assert_eq!(
+            loaded
+                .search_speaker(&embedding(&[-1.0, 0.0]), 0.5)
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn load_rejects_corrupt_files() {
+        let path = temp_path("load_rejects_corrupt_files");
+        // A model id claiming 4 GB
+        let mut bytes = b"PYSPKDB\0".to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        let error = EmbeddingManager::load(&path, "model-a").unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("truncated"));
+    }
+
+    #[test]
+    fn save_and_load_exemplars() {
+        let path = temp_path("save_and_load_exemplars");
+        let options = ExemplarOptions {
+            max_exemplars: 3,
+            scoring: ExemplarScoring::TopMean(2),
+        };
+        let mut manager = EmbeddingManager::new(8).with_exemplars(options);
+        let exemplars = [embedding(&[1.0, 0.0]), embedding(&[0.0, 1.0])];
+        manager.enroll("alice", &exemplars).unwrap();
+        manager.save(&path, "model-a").unwrap();
+        let loaded = EmbeddingManager::load(&path, "model-a").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.exemplars, Some(options));
+        assert_eq!(loaded.get_all_speakers()[&1].exemplars(), &exemplars);
+    }
+
+    #[test]
+    fn save_and_load_aging_state() {
+        let path = temp_path("save_and_load_aging_state");
+        let aging = Aging {
+            horizon: 60.0,
+            action: AgingAction::Archive,
+        };
+        let mut manager = EmbeddingManager::new(8).with_aging(aging).unwrap();
+        let (a, b) = (embedding(&[1.0, 0.0]), embedding(&[0.0, 1.0]));
+        manager.assign_segment(&a, 0.5, 0.0, 5.0).unwrap();
+        manager.assign_segment(&b, 0.5, 70.0, 75.0).unwrap();
+        manager.save(&path, "model-a").unwrap();
+        let mut loaded = EmbeddingManager::load(&path, "model-a").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.aging, Some(aging));
+        assert_eq!(loaded.now(), 75.0);
+        let archived = &loaded.archived()[&1];
+        assert_eq!(archived.last_heard(), 5.0);
+        assert_eq!(archived.speech_duration(), 5.0);
+        assert_eq!(loaded.get_all_speakers()[&2].last_heard(), 75.0);
+
+        // Aging goes on where it stopped
+        assert_eq!(loaded.advance_to(140.0).unwrap(), vec![2]);
+        loaded.restore(1).unwrap();
+        assert_eq!(loaded.label(1).as_deref(), Some("unknown-1"));
+    }
+
+    #[test]
+    fn load_rejects_invalid_state() {
+        let path = temp_path("load_rejects_invalid_state");
+        let saved = || {
+            let mut manager = EmbeddingManager::new(2);
+            manager.assign(&embedding(&[1.0, 0.0]), 0.5, 1.0).unwrap();
+            manager.assign(&embedding(&[0.0, 1.0]), 0.5, 1.0).unwrap();
+            manager
+        };
+        let invalid: [fn(&mut EmbeddingManager); 4] = [
+            |manager| manager.now = f64::NAN,
+            |manager| manager.speakers.get_mut(&1).unwrap().weight = f32::NAN,
+            |manager| manager.speakers.get_mut(&2).unwrap().last_heard = f64::INFINITY,
+            |manager| manager.max_speakers = 1,
+        ];
+        for corrupt in invalid {
+            let mut manager = saved();
+            corrupt(&mut manager);
+            manager.save(&path, "model-a").unwrap();
+            assert!(EmbeddingManager::load(&path, "model-a").is_err());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
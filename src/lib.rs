@@ -1,12 +1,28 @@
 mod session;
 
+pub mod analytics;
+pub mod calibration;
+pub mod cluster;
 mod embedding;
 mod identify;
+mod linalg;
+mod norm;
+mod plda;
+mod score;
 mod segment;
+mod verify;
 mod wav;
 
-pub use embedding::EmbeddingExtractor;
-pub use identify::EmbeddingManager;
+pub use embedding::{Embedding, EmbeddingExtractor, EmbeddingInput};
+pub use identify::{
+    Adaptation, Aging, AgingAction, Assignment, EmbeddingManager, ExemplarOptions, ExemplarScoring,
+    HnswIndex, HnswOptions, Identification, OverflowPolicy, Speaker, SpeakerMatch, SpeakerQuery,
+    SpeakerRemap, INDEX_CANDIDATES,
+};
 pub use knf_rs::{compute_fbank, convert_integer_to_float_audio};
-pub use segment::{get_segments, Segment};
+pub use norm::{Cohort, CohortStats};
+pub use plda::Plda;
+pub use score::{CosineScorer, EuclideanScorer, NormalizedScorer, Prepared, Scorer};
+pub use segment::{get_segments, get_windows, Segment, SegmentationWindow, LOCAL_SPEAKERS};
+pub use verify::{verify, verify_normalized, verify_with, Verification};
 pub use wav::read_wav;
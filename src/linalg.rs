@@ -0,0 +1,224 @@
+use ndarray::{Array1, Array2};
+
+/// Eigen decomposition of a symmetric matrix.
+///
+/// Returns eigenvalues in ascending order and the matching eigenvectors as
+/// columns. Householder tridiagonalization followed by the implicit QL
+/// algorithm (tred2 / tql2 from EISPACK).
+pub fn symmetric_eigen(a: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut v = a.clone();
+    let mut d = Array1::zeros(n);
+    let mut e = Array1::zeros(n);
+    if n == 0 {
+        return (d, v);
+    }
+    tred2(&mut v, &mut d, &mut e);
+    tql2(&mut v, &mut d, &mut e);
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| d[i].total_cmp(&d[j]));
+    let values = order.iter().map(|&i| d[i]).collect();
+    let vectors = v.select(ndarray::Axis(1), &order);
+    (values, vectors)
+}
+
+/// Symmetric Householder reduction to tridiagonal form
+fn tred2(v: &mut Array2<f64>, d: &mut Array1<f64>, e: &mut Array1<f64>) {
+    let n = d.len();
+    for j in 0..n {
+        d[j] = v[[n - 1, j]];
+    }
+
+    for i in (1..n).rev() {
+        // Scale to avoid under/overflow
+        let scale: f64 = (0..i).map(|k| d[k].abs()).sum();
+        let mut h = 0.0;
+        if scale == 0.0 {
+            e[i] = d[i - 1];
+            for j in 0..i {
+                d[j] = v[[i - 1, j]];
+                v[[i, j]] = 0.0;
+                v[[j, i]] = 0.0;
+            }
+        } else {
+            // Generate Householder vector
+            for k in 0..i {
+                d[k] /= scale;
+                h += d[k] * d[k];
+            }
+            let mut f = d[i - 1];
+            let mut g = h.sqrt();
+            if f > 0.0 {
+                g = -g;
+            }
+            e[i] = scale * g;
+            h -= f * g;
+            d[i - 1] = f - g;
+            for j in 0..i {
+                e[j] = 0.0;
+            }
+
+            // Apply similarity transformation to remaining columns
+            for j in 0..i {
+                f = d[j];
+                v[[j, i]] = f;
+                g = e[j] + v[[j, j]] * f;
+                for k in (j + 1)..i {
+                    g += v[[k, j]] * d[k];
+                    e[k] += v[[k, j]] * f;
+                }
+                e[j] = g;
+            }
+            f = 0.0;
+            for j in 0..i {
+                e[j] /= h;
+                f += e[j] * d[j];
+            }
+            let hh = f / (h + h);
+            for j in 0..i {
+                e[j] -= hh * d[j];
+            }
+            for j in 0..i {
+                f = d[j];
+                g = e[j];
+                for k in j..i {
+                    v[[k, j]] -= f * e[k] + g * d[k];
+                }
+                d[j] = v[[i - 1, j]];
+                v[[i, j]] = 0.0;
+            }
+        }
+        d[i] = h;
+    }
+
+    // Accumulate transformations
+    for i in 0..(n - 1) {
+        v[[n - 1, i]] = v[[i, i]];
+        v[[i, i]] = 1.0;
+        let h = d[i + 1];
+        if h != 0.0 {
+            for k in 0..=i {
+                d[k] = v[[k, i + 1]] / h;
+            }
+            for j in 0..=i {
+                let g: f64 = (0..=i).map(|k| v[[k, i + 1]] * v[[k, j]]).sum();
+                for k in 0..=i {
+                    v[[k, j]] -= g * d[k];
+                }
+            }
+        }
+        for k in 0..=i {
+            v[[k, i + 1]] = 0.0;
+        }
+    }
+    for j in 0..n {
+        d[j] = v[[n - 1, j]];
+        v[[n - 1, j]] = 0.0;
+    }
+    v[[n - 1, n - 1]] = 1.0;
+    e[0] = 0.0;
+}
+
+/// Symmetric tridiagonal QL algorithm
+fn tql2(v: &mut Array2<f64>, d: &mut Array1<f64>, e: &mut Array1<f64>) {
+    let n = d.len();
+    for i in 1..n {
+        e[i - 1] = e[i];
+    }
+    e[n - 1] = 0.0;
+
+    let mut f = 0.0;
+    let mut tst1: f64 = 0.0;
+    let eps = f64::EPSILON;
+    for l in 0..n {
+        // Find small subdiagonal element
+        tst1 = tst1.max(d[l].abs() + e[l].abs());
+        let mut m = l;
+        while m < n - 1 && e[m].abs() > eps * tst1 {
+            m += 1;
+        }
+
+        // If m == l, d[l] is an eigenvalue, otherwise iterate
+        if m > l {
+            loop {
+                // Compute implicit shift
+                let mut g = d[l];
+                let mut p = (d[l + 1] - g) / (2.0 * e[l]);
+                let mut r = p.hypot(1.0);
+                if p < 0.0 {
+                    r = -r;
+                }
+                d[l] = e[l] / (p + r);
+                d[l + 1] = e[l] * (p + r);
+                let dl1 = d[l + 1];
+                let mut h = g - d[l];
+                for i in (l + 2)..n {
+                    d[i] -= h;
+                }
+                f += h;
+
+                // Implicit QL transformation
+                p = d[m];
+                let mut c = 1.0;
+                let mut c2 = c;
+                let mut c3 = c;
+                let el1 = e[l + 1];
+                let mut s = 0.0;
+                let mut s2 = 0.0;
+                for i in (l..m).rev() {
+                    c3 = c2;
+                    c2 = c;
+                    s2 = s;
+                    g = c * e[i];
+                    h = c * p;
+                    r = p.hypot(e[i]);
+                    e[i + 1] = s * r;
+                    s = e[i] / r;
+                    c = p / r;
+                    p = c * d[i] - s * g;
+                    d[i + 1] = h + s * (c * g + s * d[i]);
+
+                    // Accumulate transformation
+                    for k in 0..n {
+                        h = v[[k, i + 1]];
+                        v[[k, i + 1]] = s * v[[k, i]] + c * h;
+                        v[[k, i]] = c * v[[k, i]] - s * h;
+                    }
+                }
+                p = -s * s2 * c3 * el1 * e[l] / dl1;
+                e[l] = s * p;
+                d[l] = c * p;
+
+                // Check for convergence
+                if e[l].abs() <= eps * tst1 {
+                    break;
+                }
+            }
+        }
+        d[l] += f;
+        e[l] = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn decomposes_symmetric_matrix() {
+        let a = array![[4.0, 1.0, 2.0], [1.0, 3.0, 0.5], [2.0, 0.5, 5.0]];
+        let (values, vectors) = symmetric_eigen(&a);
+        assert!(values[0] <= values[1] && values[1] <= values[2]);
+        for i in 0..3 {
+            let vector = vectors.column(i);
+            let residual = a.dot(&vector) - &vector * values[i];
+            assert!(residual.iter().all(|r| r.abs() < 1e-9));
+        }
+        let identity = vectors.t().dot(&vectors);
+        assert!((identity - Array2::<f64>::eye(3))
+            .iter()
+            .all(|x| x.abs() < 1e-9));
+    }
+}
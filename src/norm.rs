@@ -0,0 +1,139 @@
+use crate::cluster::check_dims;
+use crate::Embedding;
+use eyre::{bail, Context, Result};
+use std::path::Path;
+
+/// Impostor cohort for adaptive symmetric score normalization (AS-norm).
+///
+/// A raw similarity `s` between `a` and `b` is normalized against the
+/// `top_n` cohort embeddings most similar to each side:
+/// `0.5 * ((s - mean_a) / std_a + (s - mean_b) / std_b)`. Normalized scores
+/// are in standard deviations above impostor level, so a threshold around
+/// 2 to 4 carries over between microphones and languages.
+#[derive(Debug, Clone)]
+pub struct Cohort {
+    embeddings: Vec<Embedding>,
+    top_n: usize,
+}
+
+/// Mean and standard deviation of the closest cohort similarities
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CohortStats {
+    pub mean: f32,
+    pub std: f32,
+}
+
+impl Cohort {
+    pub fn new(embeddings: Vec<Embedding>, top_n: usize) -> Result<Self> {
+        if embeddings.is_empty() {
+            bail!("Cohort has no embeddings")
+        }
+        if top_n == 0 {
+            bail!("top_n must be at least 1")
+        }
+        check_dims(&embeddings)?;
+        Ok(Self { embeddings, top_n })
+    }
+
+    /// Load a cohort from a text file with one embedding per line, values
+    /// separated by whitespace
+    pub fn load<P: AsRef<Path>>(path: P, top_n: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cohort {}", path.display()))?;
+        let embeddings = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let values = line
+                    .split_whitespace()
+                    .map(str::parse::<f32>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("Invalid value on line {}", index + 1))?;
+                Embedding::new(values)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(embeddings, top_n)
+    }
+
+    pub fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.embeddings.is_empty()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.embeddings[0].dim()
+    }
+
+    /// Statistics of the `top_n` cohort similarities to `embedding`
+    pub fn stats(&self, embedding: &Embedding) -> Result<CohortStats> {
+        self.embeddings[0].check_dim(embedding)?;
+        let scores = self
+            .embeddings
+            .iter()
+            .map(|impostor| embedding.cosine(impostor))
+            .collect();
+        Ok(self.top_stats(scores))
+    }
+
+    /// AS-norm of a raw cosine similarity between two embeddings
+    pub fn normalize(&self, score: f32, a: &Embedding, b: &Embedding) -> Result<f32> {
+        Ok(normalize(score, self.stats(a)?, self.stats(b)?))
+    }
+
+    pub(crate) fn embeddings(&self) -> &[Embedding] {
+        &self.embeddings
+    }
+
+    /// Statistics of the `top_n` highest of the scores against each cohort
+    /// embedding
+    pub(crate) fn top_stats(&self, mut scores: Vec<f32>) -> CohortStats {
+        scores.sort_by(|a, b| b.total_cmp(a));
+        scores.truncate(self.top_n);
+
+        let n = scores.len() as f32;
+        let mean = scores.iter().sum::<f32>() / n;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / n;
+        CohortStats {
+            mean,
+            std: variance.sqrt().max(MIN_STD),
+        }
+    }
+}
+
+/// Symmetric normalization of `score` given the cohort statistics of both sides
+pub(crate) fn normalize(score: f32, a: CohortStats, b: CohortStats) -> f32 {
+    0.5 * ((score - a.mean) / a.std + (score - b.mean) / b.std)
+}
+
+/// Floor for the cohort standard deviation, e.g. with `top_n` of 1
+const MIN_STD: f32 = 1e-3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_against_closest_impostors() {
+        let impostors = (0..8)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::PI / 4.0 + 0.3;
+                Embedding::new(vec![angle.cos(), angle.sin(), 0.2]).unwrap()
+            })
+            .collect();
+        let cohort = Cohort::new(impostors, 3).unwrap();
+
+        let a = Embedding::new(vec![0.0, 0.1, 1.0]).unwrap();
+        let b = Embedding::new(vec![0.1, 0.0, 1.0]).unwrap();
+        let c = Embedding::new(vec![1.0, 0.0, 0.0]).unwrap();
+        let same = cohort.normalize(a.cosine(&b), &a, &b).unwrap();
+        let different = cohort.normalize(a.cosine(&c), &a, &c).unwrap();
+        assert!(same > 2.0);
+        assert!(different < same);
+        assert_eq!(same, cohort.normalize(b.cosine(&a), &b, &a).unwrap());
+    }
+}
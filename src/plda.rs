@@ -0,0 +1,239 @@
+use crate::linalg::symmetric_eigen;
+use crate::Embedding;
+use eyre::{bail, Context, ContextCompat, Result};
+use ndarray::{Array1, Array2, Axis};
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+/// Two covariance PLDA model for scoring speaker embeddings.
+///
+/// Embeddings are centered with `mean`, projected with `transform` (LDA /
+/// whitening), length normalized, centered with `plda_mean` and finally
+/// rotated so the within-speaker covariance becomes identity and the
+/// between-speaker covariance becomes `diag(psi)`.
+///
+/// Models are loaded from a whitespace separated text file. Each entry is a
+/// keyword, its shape and the values in row major order. `#` starts a comment.
+///
+/// ```text
+/// mean 256 <256 values>
+/// transform 128 256 <128 * 256 values>   # optional, identity by default
+/// plda_mean 128 <128 values>             # optional, zeros by default
+/// between 128 128 <128 * 128 values>
+/// within 128 128 <128 * 128 values>
+/// ```
+#[derive(Debug, Clone)]
+pub struct Plda {
+    mean: Array1<f64>,
+    transform: Array2<f64>,
+    plda_mean: Array1<f64>,
+    /// Simultaneous diagonalization of the within / between covariances
+    projection: Array2<f64>,
+    psi: Array1<f64>,
+}
+
+impl Plda {
+    pub fn new(
+        mean: Array1<f64>,
+        transform: Array2<f64>,
+        plda_mean: Array1<f64>,
+        between: Array2<f64>,
+        within: Array2<f64>,
+    ) -> Result<Self> {
+        let dim = transform.nrows();
+        if transform.ncols() != mean.len() {
+            bail!(
+                "PLDA transform is {:?} but mean has {} values",
+                transform.dim(),
+                mean.len()
+            )
+        }
+        if plda_mean.len() != dim || between.dim() != (dim, dim) || within.dim() != (dim, dim) {
+            bail!(
+                "PLDA covariances and plda_mean must match the transform output dimension {}",
+                dim
+            )
+        }
+
+        // Whiten the within-speaker covariance
+        let (within_values, within_vectors) = symmetric_eigen(&within);
+        if within_values.iter().any(|&v| v <= 0.0) {
+            bail!("PLDA within covariance is not positive definite")
+        }
+        let whitening = (&within_vectors / &within_values.mapv(f64::sqrt)).reversed_axes();
+
+        // Diagonalize the whitened between-speaker covariance, largest first
+        let (psi, vectors) = symmetric_eigen(&whitening.dot(&between).dot(&whitening.t()));
+        let psi = psi.slice(ndarray::s![..;-1]).mapv(|v| v.max(0.0));
+        let vectors = vectors.slice(ndarray::s![.., ..;-1]).to_owned();
+        let projection = vectors.t().dot(&whitening);
+
+        Ok(Self {
+            mean,
+            transform,
+            plda_mean,
+            projection,
+            psi,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read PLDA model {}", path.as_ref().display()))?;
+        content.parse()
+    }
+
+    /// Embedding dimension the model expects
+    pub fn input_dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// Dimension of the PLDA space
+    pub fn dim(&self) -> usize {
+        self.psi.len()
+    }
+
+    /// Between-speaker variances in the PLDA space, largest first
+    pub fn psi(&self) -> &Array1<f64> {
+        &self.psi
+    }
+
+    /// Project an embedding into the PLDA space
+    pub fn transform(&self, embedding: &Embedding) -> Result<Array1<f64>> {
+        if embedding.dim() != self.input_dim() {
+            bail!(
+                "Embedding dimension mismatch: {} != {}",
+                embedding.dim(),
+                self.input_dim()
+            )
+        }
+        let x = embedding.as_array().mapv(f64::from) - &self.mean;
+        let x = self.transform.dot(&x);
+        // Length normalization to sqrt(dim)
+        let norm = x.dot(&x).sqrt();
+        let x = if norm > 0.0 {
+            x * ((self.dim() as f64).sqrt() / norm)
+        } else {
+            x
+        };
+        Ok(self.projection.dot(&(x - &self.plda_mean)))
+    }
+
+    /// Log likelihood ratio that two embeddings come from the same speaker
+    pub fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+        Ok(self.score_transformed(&self.transform(a)?, &self.transform(b)?) as f32)
+    }
+
+    /// [`Plda::score`] for embeddings already passed through [`Plda::transform`]
+    pub fn score_transformed(&self, a: &Array1<f64>, b: &Array1<f64>) -> f64 {
+        self.psi
+            .iter()
+            .zip(a.iter().zip(b.iter()))
+            .map(|(&psi, (&x, &y))| {
+                let total = psi + 1.0;
+                let det = 2.0 * psi + 1.0;
+                let same =
+                    -0.5 * det.ln() - 0.5 * (total * (x * x + y * y) - 2.0 * psi * x * y) / det;
+                let different = -total.ln() - 0.5 * (x * x + y * y) / total;
+                same - different
+            })
+            .sum()
+    }
+}
+
+impl FromStr for Plda {
+    type Err = eyre::Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let mut tokens = content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(str::split_whitespace);
+        let mut entries: HashMap<String, (Vec<usize>, Vec<f64>)> = HashMap::new();
+
+        while let Some(key) = tokens.next() {
+            let rank = match key {
+                "mean" | "plda_mean" => 1,
+                "transform" | "between" | "within" => 2,
+                _ => bail!("Unknown PLDA entry `{}`", key),
+            };
+            let mut shape = Vec::with_capacity(rank);
+            for _ in 0..rank {
+                let dim = tokens
+                    .next()
+                    .with_context(|| format!("Missing shape of PLDA entry `{}`", key))?;
+                shape.push(
+                    dim.parse::<usize>().with_context(|| {
+                        format!("Invalid shape `{}` of PLDA entry `{}`", dim, key)
+                    })?,
+                );
+            }
+            let values = (0..shape.iter().product())
+                .map(|_| {
+                    let value = tokens
+                        .next()
+                        .with_context(|| format!("PLDA entry `{}` is truncated", key))?;
+                    value.parse::<f64>().with_context(|| {
+                        format!("Invalid value `{}` in PLDA entry `{}`", value, key)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            entries.insert(key.to_string(), (shape, values));
+        }
+
+        let mut take = |key: &str| entries.remove(key);
+        let (_, mean) = take("mean").context("PLDA model has no `mean`")?;
+        let mean = Array1::from_vec(mean);
+        let matrix = |(shape, values): (Vec<usize>, Vec<f64>)| {
+            Array2::from_shape_vec((shape[0], shape[1]), values).context("Invalid PLDA matrix")
+        };
+        let transform = match take("transform") {
+            Some(entry) => matrix(entry)?,
+            None => Array2::eye(mean.len()),
+        };
+        let plda_mean = match take("plda_mean") {
+            Some((_, values)) => Array1::from_vec(values),
+            None => Array1::zeros(transform.nrows()),
+        };
+        let between = matrix(take("between").context("PLDA model has no `between`")?)?;
+        let within = matrix(take("within").context("PLDA model has no `within`")?)?;
+        Self::new(mean, transform, plda_mean, between, within)
+    }
+}
+
+/// Project embeddings into the PLDA space, one row per embedding
+pub(crate) fn transform_all(plda: &Plda, embeddings: &[Embedding]) -> Result<Array2<f64>> {
+    let mut features = Array2::zeros((embeddings.len(), plda.dim()));
+    for (mut row, embedding) in features.axis_iter_mut(Axis(0)).zip(embeddings) {
+        row.assign(&plda.transform(embedding)?);
+    }
+    Ok(features)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = "
+        # 2-dim toy model
+        mean 2 0 0
+        between 2 2 4 0 0 4
+        within 2 2 0.5 0 0 0.5
+    ";
+
+    #[test]
+    fn same_speaker_scores_higher() {
+        let plda: Plda = MODEL.parse().unwrap();
+        assert_eq!(plda.dim(), 2);
+        assert!((plda.psi()[0] - 8.0).abs() < 1e-9);
+
+        let a = Embedding::new(vec![1.0, 0.1]).unwrap();
+        let b = Embedding::new(vec![0.9, 0.2]).unwrap();
+        let c = Embedding::new(vec![-0.1, 1.0]).unwrap();
+        assert!(plda.score(&a, &b).unwrap() > plda.score(&a, &c).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_model() {
+        assert!("mean 2 0".parse::<Plda>().is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::norm::normalize;
+use crate::{Cohort, CohortStats, Embedding, Plda};
+use eyre::Result;
+use ndarray::Array1;
+use std::any::Any;
+use std::sync::Arc;
+
+/// Similarity between two speaker embeddings, higher means more likely the
+/// same speaker.
+///
+/// Thresholds passed to [`crate::EmbeddingManager`] and the clustering
+/// functions are on the scale of the scorer in use.
+pub trait Scorer: std::fmt::Debug + Send + Sync {
+    fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32>;
+
+    /// Do the work that only depends on one side once, for embeddings that
+    /// are scored many times such as speaker profiles
+    fn prepare(&self, embedding: &Embedding) -> Result<Prepared> {
+        Ok(Prepared::new(embedding.clone()))
+    }
+
+    /// [`Scorer::score`] of two embeddings from [`Scorer::prepare`]
+    fn score_prepared(&self, a: &Prepared, b: &Prepared) -> Result<f32> {
+        self.score(&a.embedding, &b.embedding)
+    }
+
+    /// Embedding dimension the scorer expects, `None` for any
+    fn input_dim(&self) -> Option<usize> {
+        None
+    }
+
+    /// Whether scores rank embeddings like cosine similarity does, so an
+    /// [`crate::HnswIndex`] can retrieve candidates for them. True for
+    /// increasing functions of the cosine similarity only.
+    fn ranks_by_cosine(&self) -> bool {
+        false
+    }
+}
+
+/// Embedding with what a [`Scorer`] computed for it ahead of scoring, of
+/// any type the scorer chooses
+#[derive(Clone)]
+pub struct Prepared {
+    embedding: Embedding,
+    data: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Prepared {
+    pub fn new(embedding: Embedding) -> Self {
+        Self {
+            embedding,
+            data: None,
+        }
+    }
+
+    /// Attach what the scorer computed, replacing earlier data
+    pub fn with_data<T: Any + Send + Sync>(mut self, data: T) -> Self {
+        self.data = Some(Arc::new(data));
+        self
+    }
+
+    pub fn embedding(&self) -> &Embedding {
+        &self.embedding
+    }
+
+    /// Attached data, `None` without data or if it has another type, e.g.
+    /// when prepared by another scorer
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.as_deref()?.downcast_ref()
+    }
+}
+
+impl std::fmt::Debug for Prepared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Prepared")
+            .field("embedding", &self.embedding)
+            .field("data", &self.data.is_some())
+            .finish()
+    }
+}
+
+/// Cosine similarity, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CosineScorer;
+
+impl Scorer for CosineScorer {
+    fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+        a.cosine_similarity(b)
+    }
+
+    fn ranks_by_cosine(&self) -> bool {
+        true
+    }
+}
+
+/// Negative euclidean distance
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EuclideanScorer;
+
+impl Scorer for EuclideanScorer {
+    fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+        Ok(-a.euclidean_distance(b)?)
+    }
+}
+
+/// PLDA log likelihood ratio, prepared embeddings are projected into the
+/// PLDA space once
+impl Scorer for Plda {
+    fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+        Plda::score(self, a, b)
+    }
+
+    fn prepare(&self, embedding: &Embedding) -> Result<Prepared> {
+        Ok(Prepared::new(embedding.clone()).with_data(self.transform(embedding)?))
+    }
+
+    fn score_prepared(&self, a: &Prepared, b: &Prepared) -> Result<f32> {
+        match (a.data::<Array1<f64>>(), b.data::<Array1<f64>>()) {
+            (Some(a), Some(b)) => Ok(self.score_transformed(a, b) as f32),
+            _ => Plda::score(self, &a.embedding, &b.embedding),
+        }
+    }
+
+    fn input_dim(&self) -> Option<usize> {
+        Some(Plda::input_dim(self))
+    }
+}
+
+/// Cohort statistics of an embedding and the embedding prepared for the
+/// scorer [`NormalizedScorer`] wraps
+#[derive(Debug)]
+struct Normalized {
+    stats: CohortStats,
+    prepared: Prepared,
+}
+
+/// Another scorer normalized against an impostor cohort (AS-norm), see
+/// [`Cohort`].
+///
+/// The cohort statistics of an embedding are computed by
+/// [`Scorer::prepare`], prepare embeddings that are scored repeatedly.
+#[derive(Debug, Clone)]
+pub struct NormalizedScorer {
+    scorer: Arc<dyn Scorer>,
+    cohort: Cohort,
+    /// Cohort prepared for `scorer`
+    impostors: Vec<Prepared>,
+}
+
+impl NormalizedScorer {
+    pub fn new(scorer: Arc<dyn Scorer>, cohort: Cohort) -> Result<Self> {
+        let impostors = cohort
+            .embeddings()
+            .iter()
+            .map(|impostor| scorer.prepare(impostor))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            scorer,
+            cohort,
+            impostors,
+        })
+    }
+
+    pub fn cohort(&self) -> &Cohort {
+        &self.cohort
+    }
+}
+
+impl Scorer for NormalizedScorer {
+    fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+        self.score_prepared(&self.prepare(a)?, &self.prepare(b)?)
+    }
+
+    fn prepare(&self, embedding: &Embedding) -> Result<Prepared> {
+        let prepared = self.scorer.prepare(embedding)?;
+        let scores = self
+            .impostors
+            .iter()
+            .map(|impostor| self.scorer.score_prepared(&prepared, impostor))
+            .collect::<Result<Vec<f32>>>()?;
+        Ok(Prepared::new(embedding.clone()).with_data(Normalized {
+            stats: self.cohort.top_stats(scores),
+            prepared,
+        }))
+    }
+
+    fn score_prepared(&self, a: &Prepared, b: &Prepared) -> Result<f32> {
+        match (a.data::<Normalized>(), b.data::<Normalized>()) {
+            (Some(a), Some(b)) => Ok(normalize(
+                self.scorer.score_prepared(&a.prepared, &b.prepared)?,
+                a.stats,
+                b.stats,
+            )),
+            // Prepared by another scorer
+            _ => self.score(&a.embedding, &b.embedding),
+        }
+    }
+
+    fn input_dim(&self) -> Option<usize> {
+        Some(self.cohort.dim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scorers_attach_prepared_data() {
+        /// Cosine similarity with the norms computed once
+        #[derive(Debug)]
+        struct Cached;
+        impl Scorer for Cached {
+            fn score(&self, a: &Embedding, b: &Embedding) -> Result<f32> {
+                a.cosine_similarity(b)
+            }
+
+            fn prepare(&self, embedding: &Embedding) -> Result<Prepared> {
+                Ok(Prepared::new(embedding.clone()).with_data(embedding.normalized()))
+            }
+
+            fn score_prepared(&self, a: &Prepared, b: &Prepared) -> Result<f32> {
+                match (a.data::<Embedding>(), b.data::<Embedding>()) {
+                    (Some(a), Some(b)) => Ok(a.as_array().dot(b.as_array())),
+                    _ => self.score(a.embedding(), b.embedding()),
+                }
+            }
+        }
+
+        let a = Embedding::new(vec![3.0, 4.0]).unwrap();
+        let b = Embedding::new(vec![4.0, 3.0]).unwrap();
+        let (pa, pb) = (Cached.prepare(&a).unwrap(), Cached.prepare(&b).unwrap());
+        assert!(pa.data::<Embedding>().is_some());
+        assert!(pa.data::<f32>().is_none());
+        let score = Cached.score_prepared(&pa, &pb).unwrap();
+        assert!((score - 0.96).abs() < 1e-6);
+        // Prepared by another scorer
+        let plain = CosineScorer.prepare(&b).unwrap();
+        assert!(plain.data::<Embedding>().is_none());
+        assert!((Cached.score_prepared(&pa, &plain).unwrap() - score).abs() < 1e-6);
+    }
+}
@@ -1,8 +1,20 @@
 use crate::session;
-use eyre::{Context, ContextCompat, Result};
-use ndarray::{ArrayBase, Axis, IxDyn, ViewRepr};
+use eyre::{bail, Context, ContextCompat, Result};
+use ndarray::{Array2, ArrayBase, Axis, IxDyn, ViewRepr};
+use ort::session::Session;
 use std::{cmp::Ordering, collections::VecDeque, path::Path};
 
+/// Samples between two segmentation frames
+pub(crate) const FRAME_SIZE: usize = 270;
+/// Sample offset of the first segmentation frame
+pub(crate) const FRAME_START: usize = 721;
+
+/// Number of local speakers tracked by the segmentation model in one window
+pub const LOCAL_SPEAKERS: usize = 3;
+
+/// Local speakers active in each powerset class of segmentation-3.0
+const POWERSET: [&[usize]; 7] = [&[], &[0], &[1], &[2], &[0, 1], &[0, 2], &[1, 2]];
+
 #[derive(Debug, Clone)]
 #[repr(C)]
 pub struct Segment {
@@ -11,6 +23,63 @@ pub struct Segment {
     pub samples: Vec<i16>,
 }
 
+/// One segmentation window with its frame level local speaker activity
+#[derive(Debug, Clone)]
+pub struct SegmentationWindow {
+    /// Window start in seconds
+    pub start: f64,
+    pub samples: Vec<i16>,
+    /// Frames x local speakers
+    pub activations: Array2<bool>,
+}
+
+impl SegmentationWindow {
+    pub fn num_frames(&self) -> usize {
+        self.activations.nrows()
+    }
+
+    /// Frames where `speaker` is active, possibly overlapped by others
+    pub fn speaker_mask(&self, speaker: usize) -> Result<Vec<bool>> {
+        self.check_speaker(speaker)?;
+        Ok(self.activations.column(speaker).to_vec())
+    }
+
+    /// Frames where `speaker` is the only active speaker
+    pub fn exclusive_mask(&self, speaker: usize) -> Result<Vec<bool>> {
+        self.check_speaker(speaker)?;
+        Ok(self.exclusive_frames(speaker).collect())
+    }
+
+    /// Local speakers with at least `min_frames` exclusive frames
+    pub fn active_speakers(&self, min_frames: usize) -> Vec<usize> {
+        (0..self.activations.ncols())
+            .filter(|&speaker| {
+                self.exclusive_frames(speaker)
+                    .filter(|&active| active)
+                    .count()
+                    >= min_frames
+            })
+            .collect()
+    }
+
+    fn exclusive_frames(&self, speaker: usize) -> impl Iterator<Item = bool> + '_ {
+        self.activations
+            .outer_iter()
+            .map(move |frame| frame[speaker] && frame.iter().filter(|&&active| active).count() == 1)
+    }
+
+    fn check_speaker(&self, speaker: usize) -> Result<()> {
+        if speaker >= self.activations.ncols() {
+            bail!(
+                "Local speaker {} out of range, the window has {}",
+                speaker,
+                self.activations.ncols()
+            )
+        }
+        Ok(())
+    }
+}
+
 fn find_max_index(row: ArrayBase<ViewRepr<&f32>, IxDyn>) -> Result<usize> {
     let (max_index, _) = row
         .iter()
@@ -24,17 +93,136 @@ fn find_max_index(row: ArrayBase<ViewRepr<&f32>, IxDyn>) -> Result<usize> {
     Ok(max_index)
 }
 
+/// Load the segmentation model and check its signature against what
+/// [`run_window`] and [`decode_powerset`] expect
+fn load_model(model_path: &Path) -> Result<Session> {
+    let session = session::create_session(model_path)?;
+
+    let input = session
+        .inputs
+        .first()
+        .context("Segmentation model has no inputs")?;
+    let shape = session::check_tensor(
+        "Segmentation model input",
+        &input.name,
+        &input.input_type,
+        &[3],
+    )?;
+    if shape[1] > 1 {
+        bail!(
+            "Segmentation model input `{}` expects {} channels, only mono is supported",
+            input.name,
+            shape[1]
+        )
+    }
+
+    let output = session
+        .outputs
+        .iter()
+        .find(|output| output.name == "output")
+        .with_context(|| {
+            let names: Vec<&str> = session.outputs.iter().map(|o| o.name.as_str()).collect();
+            format!(
+                "Segmentation model has no `output` tensor, found {:?}",
+                names
+            )
+        })?;
+    let shape = session::check_tensor(
+        "Segmentation model output",
+        &output.name,
+        &output.output_type,
+        &[3],
+    )?;
+    if shape[2] >= 0 && shape[2] as usize != POWERSET.len() {
+        bail!(
+            "Segmentation model output has {} classes, expected {} powerset classes",
+            shape[2],
+            POWERSET.len()
+        )
+    }
+    Ok(session)
+}
+
+/// Run the segmentation model on one window and return the raw output
+fn run_window(session: &mut Session, window: &[i16]) -> Result<ndarray::ArrayD<f32>> {
+    // Convert window to ndarray::Array1
+    let array = ndarray::Array1::from_iter(window.iter().map(|&x| x as f32));
+    let array = array.view().insert_axis(Axis(0)).insert_axis(Axis(1));
+
+    let inputs = ort::inputs![ort::value::TensorRef::from_array_view(array.into_dyn())
+        .map_err(|e| eyre::eyre!("Failed to prepare inputs: {:?}", e))?];
+
+    let ort_outs = session
+        .run(inputs)
+        .map_err(|e| eyre::eyre!("Failed to run the session: {:?}", e))?;
+
+    let (shape, data) = ort_outs
+        .get("output")
+        .context("Output tensor not found")?
+        .try_extract_tensor::<f32>()
+        .context("Failed to extract tensor")?; // (&Shape, &[f32])
+
+    // shape is &Shape, but from_shape expects &[usize]
+    let shape_slice: Vec<usize> = shape.iter().map(|&d| d as usize).collect();
+    let view = ndarray::ArrayViewD::<f32>::from_shape(ndarray::IxDyn(&shape_slice), data)?;
+    Ok(view.to_owned())
+}
+
+/// Decode powerset output (batch x frames x classes) into frames x local speakers
+fn decode_powerset(output: &ndarray::ArrayD<f32>) -> Result<Array2<bool>> {
+    let batch = output
+        .outer_iter()
+        .next()
+        .context("Empty segmentation output")?;
+    let mut activations = Array2::from_elem((batch.len_of(Axis(0)), LOCAL_SPEAKERS), false);
+    for (frame, scores) in batch.axis_iter(Axis(0)).enumerate() {
+        let class = find_max_index(scores)?;
+        let speakers = POWERSET.get(class).context("Unexpected powerset class")?;
+        for &speaker in speakers.iter() {
+            activations[[frame, speaker]] = true;
+        }
+    }
+    Ok(activations)
+}
+
+/// Iterate over fixed 10s windows with their local speaker activations.
+///
+/// Unlike [`get_segments`], local speakers are kept apart so overlapped speech
+/// can be excluded when computing embeddings.
+pub fn get_windows<P: AsRef<Path>>(
+    samples: &[i16],
+    sample_rate: u32,
+    model_path: P,
+) -> Result<impl Iterator<Item = Result<SegmentationWindow>> + '_> {
+    let mut session = load_model(model_path.as_ref())?;
+    let window_size = (sample_rate * 10) as usize; // 10 seconds
+
+    Ok((0..samples.len()).step_by(window_size).map(move |start| {
+        let end = (start + window_size).min(samples.len());
+        // Pad the last window with silence
+        let mut window = samples[start..end].to_vec();
+        window.resize(window_size, 0);
+
+        let output = run_window(&mut session, &window)?;
+        Ok(SegmentationWindow {
+            start: start as f64 / sample_rate as f64,
+            samples: window,
+            activations: decode_powerset(&output)?,
+        })
+    }))
+}
+
 pub fn get_segments<P: AsRef<Path>>(
     samples: &[i16],
     sample_rate: u32,
     model_path: P,
 ) -> Result<impl Iterator<Item = Result<Segment>> + '_> {
     // Create session using the provided model path
-    let mut session = session::create_session(model_path.as_ref())?;
+    let mut session = load_model(model_path.as_ref())?;
 
     // Define frame parameters
-    let frame_size = 270;
-    let frame_start = 721;
+    let frame_size = FRAME_SIZE;
+    let frame_start = FRAME_START;
     let window_size = (sample_rate * 10) as usize; // 10 seconds
     let mut is_speeching = false;
     let mut offset = frame_start;
@@ -55,39 +243,11 @@ pub fn get_segments<P: AsRef<Path>>(
             let end = (start + window_size).min(padded_samples.len());
             let window = &padded_samples[start..end];
 
-            // Convert window to ndarray::Array1
-            let array = ndarray::Array1::from_iter(window.iter().map(|&x| x as f32));
-            let array = array.view().insert_axis(Axis(0)).insert_axis(Axis(1));
-
-            // Handle potential errors during the session and input processing
-            let inputs = ort::inputs![ort::value::TensorRef::from_array_view(array.into_dyn())
-                .map_err(|e| eyre::eyre!("Failed to prepare inputs: {:?}", e))
-                .ok()?];
-
-            let ort_outs = match session.run(inputs) {
-                Ok(outputs) => outputs,
-                Err(e) => return Some(Err(eyre::eyre!("Failed to run the session: {:?}", e))),
+            let view = match run_window(&mut session, window) {
+                Ok(view) => view,
+                Err(e) => return Some(Err(e)),
             };
 
-            let ort_out = match ort_outs.get("output").context("Output tensor not found") {
-                Ok(output) => output,
-                Err(e) => return Some(Err(eyre::eyre!("Output tensor error: {:?}", e))),
-            };
-
-            let ort_out = match ort_out
-                .try_extract_tensor::<f32>()
-                .context("Failed to extract tensor")
-            {
-                Ok(tensor) => tensor,
-                Err(e) => return Some(Err(eyre::eyre!("Tensor extraction error: {:?}", e))),
-            };
-
-            let (shape, data) = ort_out; // (&Shape, &[f32])
-                                         // Fix: shape is &Shape, but from_shape expects &[usize]
-            let shape_slice: Vec<usize> = (0..shape.len()).map(|i| shape[i] as usize).collect();
-            let view =
-                ndarray::ArrayViewD::<f32>::from_shape(ndarray::IxDyn(&shape_slice), data).unwrap();
-
             for row in view.outer_iter() {
                 for sub_row in row.axis_iter(Axis(0)) {
                     let max_index = match find_max_index(sub_row) {
@@ -129,3 +289,61 @@ pub fn get_segments<P: AsRef<Path>>(
         segments_queue.pop_front().map(Ok)
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Batch of one window with the given powerset class per frame
+    fn powerset_output(classes: &[usize]) -> ndarray::ArrayD<f32> {
+        let mut output =
+            ndarray::ArrayD::zeros(ndarray::IxDyn(&[1, classes.len(), POWERSET.len()]));
+        for (frame, &class) in classes.iter().enumerate() {
+            output[[0, frame, class]] = 1.0;
+        }
+        output
+    }
+
+    #[test]
+    fn decodes_powerset_classes() {
+        let activations = decode_powerset(&powerset_output(&[0, 1, 4, 6, 3])).unwrap();
+        let expected = [
+            [false, false, false],
+            [true, false, false],
+            [true, true, false],
+            [false, true, true],
+            [false, false, true],
+        ];
+        assert_eq!(activations.nrows(), expected.len());
+        for (frame, speakers) in expected.iter().enumerate() {
+            assert_eq!(activations.row(frame).to_vec(), speakers.to_vec());
+        }
+
+        let window = SegmentationWindow {
+            start: 0.0,
+            samples: Vec::new(),
+            activations,
+        };
+        assert_eq!(
+            window.exclusive_mask(0).unwrap(),
+            vec![false, true, false, false, false]
+        );
+        assert_eq!(window.active_speakers(1), vec![0, 2]);
+        assert!(window.speaker_mask(LOCAL_SPEAKERS).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_powerset_class() {
+        let mut output = powerset_output(&[0]);
+        output = ndarray::concatenate(
+            Axis(2),
+            &[
+                output.view(),
+                ndarray::ArrayD::ones(ndarray::IxDyn(&[1, 1, 1])).view(),
+            ],
+        )
+        .unwrap();
+        assert!(decode_powerset(&output).is_err());
+        assert!(decode_powerset(&ndarray::ArrayD::zeros(ndarray::IxDyn(&[0, 0, 7]))).is_err());
+    }
+}
@@ -1,14 +1,64 @@
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
-use eyre::Result;
+use eyre::{bail, Context, Result};
 use ort::session::builder::GraphOptimizationLevel;
 use ort::session::Session;
+use ort::tensor::TensorElementType;
+use ort::value::ValueType;
 
 pub fn create_session<P: AsRef<Path>>(path: P) -> Result<Session> {
     let session = Session::builder()?
         .with_optimization_level(GraphOptimizationLevel::Level3)?
         .with_intra_threads(1)?
         .with_inter_threads(1)?
-        .commit_from_file(path.as_ref())?;
+        .commit_from_file(path.as_ref())
+        .with_context(|| format!("Failed to load model {}", path.as_ref().display()))?;
     Ok(session)
 }
+
+/// Stable fingerprint of a model file (64 bit FNV-1a of its content)
+pub fn model_id<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = File::open(path.as_ref())
+        .with_context(|| format!("Failed to open model {}", path.as_ref().display()))?;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+/// Check that a model input or output is an f32 tensor with one of `ranks`
+/// and return its shape (dynamic dimensions are -1)
+pub fn check_tensor(
+    kind: &str,
+    name: &str,
+    value_type: &ValueType,
+    ranks: &[usize],
+) -> Result<Vec<i64>> {
+    let ValueType::Tensor { ty, shape, .. } = value_type else {
+        bail!("{} `{}` is {}, expected a tensor", kind, name, value_type)
+    };
+    if *ty != TensorElementType::Float32 {
+        bail!("{} `{}` has type {}, expected f32", kind, name, ty)
+    }
+    if !ranks.contains(&shape.len()) {
+        bail!(
+            "{} `{}` has shape {}, expected rank {:?}",
+            kind,
+            name,
+            shape,
+            ranks
+        )
+    }
+    Ok(shape.to_vec())
+}
@@ -0,0 +1,116 @@
+use crate::{Cohort, Embedding, EmbeddingExtractor, Scorer};
+use eyre::{bail, Result};
+
+/// Sample rate the embedding models expect
+const SAMPLE_RATE: u32 = 16000;
+
+/// Outcome of a speaker verification trial
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Verification {
+    pub score: f32,
+    /// Whether `score` is above the threshold
+    pub accepted: bool,
+}
+
+impl Verification {
+    fn new(score: f32, threshold: f32) -> Self {
+        Self {
+            score,
+            accepted: score > threshold,
+        }
+    }
+}
+
+/// Check whether two embeddings come from the same speaker by cosine
+/// similarity. `threshold` is the operating point, e.g. from
+/// [`crate::calibration::calibrate`].
+pub fn verify(a: &Embedding, b: &Embedding, threshold: f32) -> Result<Verification> {
+    Ok(Verification::new(a.cosine_similarity(b)?, threshold))
+}
+
+/// [`verify`] with another [`Scorer`], `threshold` is on its scale
+pub fn verify_with(
+    scorer: &dyn Scorer,
+    a: &Embedding,
+    b: &Embedding,
+    threshold: f32,
+) -> Result<Verification> {
+    Ok(Verification::new(scorer.score(a, b)?, threshold))
+}
+
+/// [`verify`] with the score normalized against an impostor cohort
+pub fn verify_normalized(
+    a: &Embedding,
+    b: &Embedding,
+    cohort: &Cohort,
+    threshold: f32,
+) -> Result<Verification> {
+    let score = cohort.normalize(a.cosine_similarity(b)?, a, b)?;
+    Ok(Verification::new(score, threshold))
+}
+
+impl EmbeddingExtractor {
+    /// Check whether two clips of 16 kHz mono audio are the same speaker.
+    /// Fails for other sample rates.
+    pub fn verify(
+        &mut self,
+        a: &[i16],
+        b: &[i16],
+        sample_rate: u32,
+        threshold: f32,
+    ) -> Result<Verification> {
+        if sample_rate != SAMPLE_RATE {
+            bail!(
+                "Expected {} Hz audio, got {} Hz, resample it first",
+                SAMPLE_RATE,
+                sample_rate
+            )
+        }
+        let a = self.compute(a)?;
+        let b = self.compute(b)?;
+        verify(&a, &b, threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanScorer;
+
+    fn embedding(values: &[f32]) -> Embedding {
+        Embedding::new(values.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn verifies_by_threshold() {
+        let (a, b) = (embedding(&[1.0, 0.0]), embedding(&[0.8, 0.6]));
+        let verification = verify(&a, &b, 0.7).unwrap();
+        assert!((verification.score - 0.8).abs() < 1e-6);
+        assert!(verification.accepted);
+        assert!(!verify(&a, &b, 0.8).unwrap().accepted);
+        assert!(verify(&a, &embedding(&[1.0, 0.0, 0.0]), 0.5).is_err());
+
+        let verification = verify_with(&EuclideanScorer, &a, &b, -0.5).unwrap();
+        assert!((verification.score + 0.4f32.sqrt()).abs() < 1e-6);
+        assert!(!verification.accepted);
+    }
+
+    #[test]
+    fn normalizes_against_cohort() {
+        let cohort = Cohort::new(
+            vec![
+                embedding(&[0.0, 1.0]),
+                embedding(&[0.0, -1.0]),
+                embedding(&[-1.0, 0.0]),
+            ],
+            3,
+        )
+        .unwrap();
+        let (a, b) = (embedding(&[1.0, 0.0]), embedding(&[0.8, 0.6]));
+        // Cohort scores are 0, 0, -1 for a and 0.6, -0.6, -0.8 for b
+        let normalized = verify_normalized(&a, &b, &cohort, 2.0).unwrap();
+        assert!((normalized.score - 2.0647).abs() < 1e-3);
+        assert!(normalized.accepted);
+        assert!(!verify_normalized(&a, &b, &cohort, 2.1).unwrap().accepted);
+    }
+}